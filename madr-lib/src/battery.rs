@@ -1,4 +1,5 @@
 use crate::device::Device;
+use crate::packet::Packet;
 use crate::{MadRError, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -11,28 +12,18 @@ pub struct Battery {
 impl Battery {
     /// Read battery status from the device
     pub fn read(device: &Device) -> Result<Self> {
-        let mut report = [0u8; 17];
-        report[0] = 0x08;
-        report[1] = 0x04;
-        report[16] = 0x55 - (0x08 + report[1]);
-
-        device.write(&report)?;
-
-        let mut buf = [0u8; 256];
-        let size = device.read_timeout(&mut buf, 20)?;
-
-        let data = &buf[..size];
-        Self::parse_report(data)
+        let response = device.request(&Packet::battery())?;
+        Self::parse_report(response.payload())
     }
 
-    fn parse_report(data: &[u8]) -> Result<Self> {
-        if data.len() < 17 || data[0] != 0x08 || data[1] != 0x04 {
+    pub(crate) fn parse_report(data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
             return Err(MadRError::InvalidBatteryFormat);
         }
 
-        let percentage = data[6];
-        let is_charging = data[7] == 0x01;
-        let voltage_mv = u16::from_be_bytes([data[8], data[9]]);
+        let percentage = data[0];
+        let is_charging = data[1] == 0x01;
+        let voltage_mv = u16::from_be_bytes([data[2], data[3]]);
 
         Ok(Battery {
             percentage,
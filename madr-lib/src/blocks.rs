@@ -0,0 +1,152 @@
+// Map of the configuration memory blocks the crate knows how to decode.
+// Used to annotate raw memory in snapshots and captures.
+
+#[cfg(feature = "experimental")]
+use crate::buttons::{Button, Buttons};
+use crate::debounce::Debounce;
+use crate::dpi;
+#[cfg(feature = "experimental")]
+use crate::lighting::Lighting;
+use crate::lod::LiftOffDistance;
+use crate::packet;
+use crate::performance::Performance;
+use crate::sensor::SensorMode;
+use crate::sleep::SleepTimeout;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    address: u8,
+    len: u8,
+    name: String,
+}
+
+impl Block {
+    fn new(address: u8, len: u8, name: impl Into<String>) -> Self {
+        Self {
+            address,
+            len,
+            name: name.into(),
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn length(&self) -> u8 {
+        self.len
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn contains(&self, address: u8) -> bool {
+        (self.address..self.address.saturating_add(self.len)).contains(&address)
+    }
+
+    /// Decode the raw contents of the whole block, check bytes included
+    pub fn describe(&self, bytes: &[u8]) -> Option<String> {
+        if bytes.len() != self.len as usize {
+            return None;
+        }
+
+        match self.address {
+            0x00 => {
+                let p = Performance::from_bytes(bytes).ok()?;
+                Some(format!(
+                    "polling rate {} Hz, DPI stage {} of {}",
+                    p.polling_rate() as u16,
+                    p.dpi_stage(),
+                    p.stage_count()
+                ))
+            }
+            0x0A => {
+                let fields = packet::fields(bytes, 2).ok()?;
+                let lod = LiftOffDistance::try_from(fields[0][0]).ok()?;
+                Some(format!("lift-off distance {}", lod))
+            }
+            0x0C..0x2C => {
+                let (a, b) = dpi::decode_dpi_pair(bytes).ok()?;
+                Some(format!("{}, {}", a, b))
+            }
+            0x2C..0x4C => {
+                let (a, b) = dpi::decode_rgb_pair(bytes).ok()?;
+                Some(format!("{} / {}", a, b))
+            }
+            #[cfg(feature = "experimental")]
+            0x4C => {
+                let l = Lighting::from_bytes(bytes).ok()?;
+                Some(format!(
+                    "{}, brightness {}, speed {}",
+                    l.effect(),
+                    l.brightness(),
+                    l.speed()
+                ))
+            }
+            #[cfg(feature = "experimental")]
+            0x60 => {
+                let buttons = Buttons::from_bytes(bytes).ok()?;
+                let actions: Vec<String> = Button::ALL
+                    .iter()
+                    .map(|b| format!("{} {}", b, buttons.action(*b)))
+                    .collect();
+                Some(actions.join(", "))
+            }
+            0xA9 => {
+                let fields = packet::fields(bytes, 2).ok()?;
+                let debounce = Debounce::try_from(fields[0][0]).ok()?;
+                Some(format!(
+                    "debounce {} ms, motion sync {}, sleep {}, angle snapping {}, ripple control {}",
+                    debounce as u8,
+                    on_off(fields[1][0]),
+                    SleepTimeout::try_from(fields[2][0]).ok()?,
+                    on_off(fields[3][0]),
+                    on_off(fields[4][0])
+                ))
+            }
+            0xB5 => {
+                let fields = packet::fields(bytes, 2).ok()?;
+                let mode = SensorMode::try_from(fields[2][0]).ok()?;
+                Some(format!("sensor mode {}", mode))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn on_off(flag: u8) -> &'static str {
+    if flag == 0 { "off" } else { "on" }
+}
+
+/// Every block the crate knows about, in address order
+pub fn known_blocks() -> Vec<Block> {
+    let mut blocks = vec![
+        Block::new(0x00, 6, "performance"),
+        Block::new(0x0A, 2, "LOD (unconfirmed)"),
+    ];
+
+    for i in 0..4u8 {
+        let name = format!("DPI stages {}+{}", i * 2 + 1, i * 2 + 2);
+        blocks.push(Block::new(0x0C + i * 8, 8, name));
+    }
+
+    for i in 0..4u8 {
+        let name = format!("RGB stages {}+{}", i * 2 + 1, i * 2 + 2);
+        blocks.push(Block::new(0x2C + i * 8, 8, name));
+    }
+
+    #[cfg(feature = "experimental")]
+    blocks.push(Block::new(0x4C, 8, "lighting"));
+    #[cfg(feature = "experimental")]
+    blocks.push(Block::new(0x60, 24, "buttons"));
+    blocks.push(Block::new(0xA9, 10, "sleep/debounce"));
+    blocks.push(Block::new(0xB5, 6, "sensor"));
+
+    blocks
+}
+
+/// The known block containing `address`, if any
+pub fn find(address: u8) -> Option<Block> {
+    known_blocks().into_iter().find(|b| b.contains(address))
+}
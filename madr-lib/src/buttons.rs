@@ -0,0 +1,394 @@
+// Button remapping
+// The key assignment table starts at 0x60 with one 4-byte group per button, in the order
+// of `Button`: an action type, two parameter bytes and a check byte, like the DPI table.
+// The address and the encodings below are unconfirmed: no capture of the web hub's key
+// assignment page has been decoded yet, so the whole module needs the `experimental`
+// feature.
+//
+// | Type | Action   | Parameters                                   |
+// |------|----------|----------------------------------------------|
+// | 0x00 | disabled | -                                            |
+// | 0x01 | mouse    | button mask (left 0x01 ... forward 0x10)     |
+// | 0x02 | DPI      | 0x01 cycle, 0x02 up, 0x03 down               |
+// | 0x05 | keyboard | modifier mask, HID keyboard usage            |
+// | 0x06 | media    | HID consumer usage, little endian            |
+
+use crate::device::Device;
+use crate::packet::{self, Packet};
+use crate::{MadRError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+const TABLE_ADDRESS: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Button {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+    Dpi,
+}
+
+impl Button {
+    pub const ALL: [Button; 6] = [
+        Button::Left,
+        Button::Right,
+        Button::Middle,
+        Button::Back,
+        Button::Forward,
+        Button::Dpi,
+    ];
+
+    /// What the button does out of the box
+    pub fn default_action(&self) -> ButtonAction {
+        match self {
+            Button::Left => ButtonAction::Mouse(MouseButton::Left),
+            Button::Right => ButtonAction::Mouse(MouseButton::Right),
+            Button::Middle => ButtonAction::Mouse(MouseButton::Middle),
+            Button::Back => ButtonAction::Mouse(MouseButton::Back),
+            Button::Forward => ButtonAction::Mouse(MouseButton::Forward),
+            Button::Dpi => ButtonAction::DpiCycle,
+        }
+    }
+
+    fn address(&self) -> u8 {
+        TABLE_ADDRESS + *self as u8 * 4
+    }
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Button::Left => write!(f, "left"),
+            Button::Right => write!(f, "right"),
+            Button::Middle => write!(f, "middle"),
+            Button::Back => write!(f, "back"),
+            Button::Forward => write!(f, "forward"),
+            Button::Dpi => write!(f, "dpi"),
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = MadRError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Button::ALL
+            .into_iter()
+            .find(|b| b.to_string() == s.to_lowercase())
+            .ok_or_else(|| MadRError::InvalidButtonAction(format!("Unknown button: {}", s)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left = 0x01,
+    Right = 0x02,
+    Middle = 0x04,
+    Back = 0x08,
+    Forward = 0x10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKey {
+    PlayPause = 0xCD,
+    Next = 0xB5,
+    Previous = 0xB6,
+    Stop = 0xB7,
+    Mute = 0xE2,
+    VolumeUp = 0xE9,
+    VolumeDown = 0xEA,
+}
+
+/// Keyboard modifier mask, as in a HID boot keyboard report
+pub mod modifiers {
+    pub const CTRL: u8 = 0x01;
+    pub const SHIFT: u8 = 0x02;
+    pub const ALT: u8 = 0x04;
+    pub const GUI: u8 = 0x08;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonAction {
+    Disabled,
+    Mouse(MouseButton),
+    /// HID keyboard usage with a modifier mask (see [`modifiers`])
+    Key {
+        modifiers: u8,
+        code: u8,
+    },
+    Media(MediaKey),
+    DpiCycle,
+    DpiUp,
+    DpiDown,
+}
+
+impl ButtonAction {
+    fn encode(&self) -> [u8; 3] {
+        match *self {
+            ButtonAction::Disabled => [0x00, 0x00, 0x00],
+            ButtonAction::Mouse(button) => [0x01, button as u8, 0x00],
+            ButtonAction::DpiCycle => [0x02, 0x01, 0x00],
+            ButtonAction::DpiUp => [0x02, 0x02, 0x00],
+            ButtonAction::DpiDown => [0x02, 0x03, 0x00],
+            ButtonAction::Key { modifiers, code } => [0x05, modifiers, code],
+            ButtonAction::Media(key) => {
+                let [low, high] = (key as u16).to_le_bytes();
+                [0x06, low, high]
+            }
+        }
+    }
+
+    fn decode(field: &[u8]) -> Result<Self> {
+        let unknown =
+            || MadRError::InvalidButtonAction(format!("Unknown key assignment: {:02x?}", field));
+
+        let action = match (field[0], field[1], field[2]) {
+            (0x00, _, _) => ButtonAction::Disabled,
+            (0x01, mask, _) => {
+                let button = MOUSE_BUTTONS
+                    .iter()
+                    .find(|(b, _)| *b as u8 == mask)
+                    .ok_or_else(unknown)?;
+                ButtonAction::Mouse(button.0)
+            }
+            (0x02, 0x01, _) => ButtonAction::DpiCycle,
+            (0x02, 0x02, _) => ButtonAction::DpiUp,
+            (0x02, 0x03, _) => ButtonAction::DpiDown,
+            (0x05, modifiers, code) => ButtonAction::Key { modifiers, code },
+            (0x06, low, high) => {
+                let usage = u16::from_le_bytes([low, high]);
+                let key = MEDIA_KEYS
+                    .iter()
+                    .find(|(k, _)| *k as u16 == usage)
+                    .ok_or_else(unknown)?;
+                ButtonAction::Media(key.0)
+            }
+            _ => return Err(unknown()),
+        };
+
+        Ok(action)
+    }
+}
+
+const MOUSE_BUTTONS: [(MouseButton, &str); 5] = [
+    (MouseButton::Left, "left-click"),
+    (MouseButton::Right, "right-click"),
+    (MouseButton::Middle, "middle-click"),
+    (MouseButton::Back, "back"),
+    (MouseButton::Forward, "forward"),
+];
+
+const MEDIA_KEYS: [(MediaKey, &str); 7] = [
+    (MediaKey::PlayPause, "play-pause"),
+    (MediaKey::Next, "next"),
+    (MediaKey::Previous, "previous"),
+    (MediaKey::Stop, "stop"),
+    (MediaKey::Mute, "mute"),
+    (MediaKey::VolumeUp, "volume-up"),
+    (MediaKey::VolumeDown, "volume-down"),
+];
+
+const MODIFIER_NAMES: [(u8, &str); 4] = [
+    (modifiers::CTRL, "ctrl"),
+    (modifiers::SHIFT, "shift"),
+    (modifiers::ALT, "alt"),
+    (modifiers::GUI, "gui"),
+];
+
+const NAMED_KEYS: [(u8, &str); 17] = [
+    (0x28, "enter"),
+    (0x29, "esc"),
+    (0x2A, "backspace"),
+    (0x2B, "tab"),
+    (0x2C, "space"),
+    (0x39, "capslock"),
+    (0x46, "printscreen"),
+    (0x49, "insert"),
+    (0x4A, "home"),
+    (0x4B, "pageup"),
+    (0x4C, "delete"),
+    (0x4D, "end"),
+    (0x4E, "pagedown"),
+    (0x4F, "right"),
+    (0x50, "left"),
+    (0x51, "down"),
+    (0x52, "up"),
+];
+
+fn key_name(code: u8) -> String {
+    match code {
+        0x04..=0x1D => ((b'a' + code - 0x04) as char).to_string(),
+        0x1E..=0x26 => ((b'1' + code - 0x1E) as char).to_string(),
+        0x27 => "0".into(),
+        0x3A..=0x45 => format!("f{}", code - 0x39),
+        _ => NAMED_KEYS
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map_or_else(|| format!("{:#04x}", code), |(_, name)| name.to_string()),
+    }
+}
+
+fn parse_key(name: &str) -> Option<u8> {
+    let bytes = name.as_bytes();
+    match bytes {
+        [c @ b'a'..=b'z'] => Some(c - b'a' + 0x04),
+        [b'0'] => Some(0x27),
+        [c @ b'1'..=b'9'] => Some(c - b'1' + 0x1E),
+        _ if name.starts_with("0x") => u8::from_str_radix(&name[2..], 16).ok(),
+        _ if name.starts_with('f') => match name[1..].parse::<u8>() {
+            Ok(n @ 1..=12) => Some(0x39 + n),
+            _ => None,
+        },
+        _ => NAMED_KEYS.iter().find(|(_, n)| *n == name).map(|(c, _)| *c),
+    }
+}
+
+impl fmt::Display for ButtonAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ButtonAction::Disabled => write!(f, "disabled"),
+            ButtonAction::Mouse(button) => {
+                let name = MOUSE_BUTTONS.iter().find(|(b, _)| b == button).unwrap().1;
+                write!(f, "{}", name)
+            }
+            ButtonAction::Key { modifiers, code } => {
+                let mut parts: Vec<String> = MODIFIER_NAMES
+                    .iter()
+                    .filter(|(mask, _)| modifiers & mask != 0)
+                    .map(|(_, name)| name.to_string())
+                    .collect();
+                parts.push(key_name(*code));
+                write!(f, "key:{}", parts.join("+"))
+            }
+            ButtonAction::Media(key) => {
+                let name = MEDIA_KEYS.iter().find(|(k, _)| k == key).unwrap().1;
+                write!(f, "media:{}", name)
+            }
+            ButtonAction::DpiCycle => write!(f, "dpi-cycle"),
+            ButtonAction::DpiUp => write!(f, "dpi-up"),
+            ButtonAction::DpiDown => write!(f, "dpi-down"),
+        }
+    }
+}
+
+/// Parses the same form [`Display`](fmt::Display) produces: `left-click`, `disabled`,
+/// `dpi-cycle`, `media:volume-up`, `key:ctrl+shift+f5`, or `key:0x68` for any other HID usage
+impl FromStr for ButtonAction {
+    type Err = MadRError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.to_lowercase();
+        let invalid = || MadRError::InvalidButtonAction(s.clone());
+
+        if let Some(keys) = s.strip_prefix("key:") {
+            let mut parts: Vec<&str> = keys.split('+').collect();
+            let code = parse_key(parts.pop().ok_or_else(invalid)?).ok_or_else(invalid)?;
+
+            let mut modifiers = 0;
+            for part in parts {
+                let (mask, _) = MODIFIER_NAMES
+                    .iter()
+                    .find(|(_, name)| *name == part)
+                    .ok_or_else(invalid)?;
+                modifiers |= mask;
+            }
+
+            return Ok(ButtonAction::Key { modifiers, code });
+        }
+
+        if let Some(name) = s.strip_prefix("media:") {
+            let (key, _) = MEDIA_KEYS
+                .iter()
+                .find(|(_, n)| *n == name)
+                .ok_or_else(invalid)?;
+            return Ok(ButtonAction::Media(*key));
+        }
+
+        match s.as_str() {
+            "disabled" => Ok(ButtonAction::Disabled),
+            "dpi-cycle" => Ok(ButtonAction::DpiCycle),
+            "dpi-up" => Ok(ButtonAction::DpiUp),
+            "dpi-down" => Ok(ButtonAction::DpiDown),
+            _ => MOUSE_BUTTONS
+                .iter()
+                .find(|(_, n)| *n == s)
+                .map(|(b, _)| ButtonAction::Mouse(*b))
+                .ok_or_else(invalid),
+        }
+    }
+}
+
+/// Key assignment of every button
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Buttons {
+    actions: Vec<ButtonAction>,
+}
+
+impl Buttons {
+    /// Read key assignments from device
+    pub fn read(device: &Device) -> Result<Self> {
+        let block = device.read_block(TABLE_ADDRESS, Button::ALL.len() * 4)?;
+        Self::from_bytes(&block)
+    }
+
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self> {
+        let actions = packet::fields(data, 4)?
+            .into_iter()
+            .map(ButtonAction::decode)
+            .collect::<Result<_>>()?;
+
+        Ok(Self { actions })
+    }
+
+    pub fn action(&self, button: Button) -> ButtonAction {
+        self.actions[button as usize]
+    }
+}
+
+/// Assign an action to a button
+pub fn apply_setting(device: &Device, button: Button, action: ButtonAction) -> Result<()> {
+    let report = Packet::write_fields(button.address(), &[&action.encode()]);
+    device.send(&report)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimulatedMouse;
+
+    #[test]
+    fn fresh_table_decodes_to_defaults() {
+        let (_, device) = SimulatedMouse::device();
+        let buttons = Buttons::read(&device).unwrap();
+
+        for button in Button::ALL {
+            assert_eq!(buttons.action(button), button.default_action());
+        }
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        let (_, device) = SimulatedMouse::device();
+        let actions = [
+            (Button::Back, "key:ctrl+c"),
+            (Button::Forward, "media:volume-up"),
+            (Button::Middle, "disabled"),
+            (Button::Dpi, "dpi-down"),
+        ];
+
+        for (button, action) in actions {
+            apply_setting(&device, button, action.parse().unwrap()).unwrap();
+        }
+
+        let buttons = Buttons::read(&device).unwrap();
+        for (button, action) in actions {
+            assert_eq!(buttons.action(button).to_string(), action);
+        }
+        assert_eq!(buttons.action(Button::Left), Button::Left.default_action());
+    }
+}
@@ -0,0 +1,571 @@
+// Decoder for USB captures of the mouse, e.g. of the vendor web hub configuring it.
+//
+// Accepted inputs:
+// - usbmon text (`cat /sys/kernel/debug/usb/usbmon/<bus>u`)
+// - pcap and pcapng with the Linux usbmon link types (Wireshark/tcpdump on `usbmonN`)
+// - text traces written by `TraceTransport`
+//
+// Only 17-byte reports with report ID 0x08 are kept, everything else on the bus is skipped.
+
+use std::time::Duration;
+
+use crate::battery::Battery;
+use crate::blocks;
+use crate::packet::{Command, PACKET_LEN, Packet, REPORT_ID};
+use crate::trace;
+use crate::{MadRError, Result};
+
+const LINKTYPE_USB_LINUX: u32 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToDevice,
+    FromDevice,
+}
+
+/// A 0x08 report picked out of a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedReport {
+    timestamp: Duration,
+    direction: Direction,
+    data: Vec<u8>,
+}
+
+impl CapturedReport {
+    /// Time since the first report in the capture
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Human-readable decode, e.g. "write DPI stages 3+4: 1600x1600, 800x800"
+    pub fn describe(&self) -> String {
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let packet = match Packet::decode(&self.data) {
+            Ok(packet) => packet,
+            Err(MadRError::ChecksumMismatch { .. }) => {
+                return format!("bad checksum: {}", hex(&self.data));
+            }
+            Err(_) => return format!("unknown report: {}", hex(&self.data)),
+        };
+
+        let (address, length, payload) = (packet.address(), packet.length(), packet.payload());
+        let block = blocks::find(address);
+        let exact = block
+            .as_ref()
+            .filter(|b| b.address() == address && b.length() == length);
+
+        let target = match (exact, &block) {
+            (Some(b), _) => b.name().to_string(),
+            (None, Some(b)) => format!("{} (partial, {:#04x} len {})", b.name(), address, length),
+            (None, None) => format!("unknown block {:#04x} len {}", address, length),
+        };
+        let contents = exact
+            .and_then(|b| b.describe(payload))
+            .unwrap_or_else(|| hex(payload));
+
+        match (packet.command(), self.direction) {
+            (Command::Write, _) => format!("write {}: {}", target, contents),
+            (Command::Read, Direction::ToDevice) => format!("read {}", target),
+            (Command::Read, Direction::FromDevice) => format!("{}: {}", target, contents),
+            (Command::Battery, Direction::ToDevice) => "read battery".into(),
+            (Command::Battery, Direction::FromDevice) => match Battery::parse_report(payload) {
+                Ok(b) => format!(
+                    "battery: {}%, {:.2}V, {}",
+                    b.percentage(),
+                    b.voltage() as f32 / 1000.0,
+                    if b.is_charging() {
+                        "charging"
+                    } else {
+                        "not charging"
+                    }
+                ),
+                Err(_) => format!("battery: {}", hex(payload)),
+            },
+        }
+    }
+}
+
+/// Extract every 0x08 report from a capture, detecting its format
+pub fn parse(bytes: &[u8]) -> Result<Vec<CapturedReport>> {
+    let mut reports = match bytes.get(..4) {
+        Some([0x0A, 0x0D, 0x0D, 0x0A]) => parse_pcapng(bytes)?,
+        Some([0xD4, 0xC3, 0xB2, 0xA1] | [0xA1, 0xB2, 0xC3, 0xD4])
+        | Some([0x4D, 0x3C, 0xB2, 0xA1] | [0xA1, 0xB2, 0x3C, 0x4D]) => parse_pcap(bytes)?,
+        _ => {
+            let text = std::str::from_utf8(bytes)
+                .map_err(|_| MadRError::InvalidCapture("Unrecognized capture format".into()))?;
+            parse_text(text)?
+        }
+    };
+
+    if let Some(start) = reports.first().map(|r| r.timestamp) {
+        for r in &mut reports {
+            r.timestamp = r.timestamp.saturating_sub(start);
+        }
+    }
+
+    Ok(reports)
+}
+
+fn report(timestamp: Duration, direction: Direction, data: &[u8]) -> Option<CapturedReport> {
+    if data.len() < PACKET_LEN || data[0] != REPORT_ID {
+        return None;
+    }
+
+    Some(CapturedReport {
+        timestamp,
+        direction,
+        data: data[..PACKET_LEN].to_vec(),
+    })
+}
+
+fn parse_text(text: &str) -> Result<Vec<CapturedReport>> {
+    let is_trace = text
+        .lines()
+        .find(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .is_some_and(|direction| direction == ">" || direction == "<");
+
+    if is_trace {
+        return Ok(trace::parse_text(text)?
+            .iter()
+            .filter_map(|event| {
+                let direction = if event.kind().is_outgoing() {
+                    Direction::ToDevice
+                } else {
+                    Direction::FromDevice
+                };
+                report(event.elapsed(), direction, event.data())
+            })
+            .collect());
+    }
+
+    Ok(text.lines().filter_map(parse_usbmon_line).collect())
+}
+
+// e.g. `ffff8881 3575914555 S Co:1:005:0 s 21 09 0308 0001 0011 17 = 08070000 0c080f0f ...`
+fn parse_usbmon_line(line: &str) -> Option<CapturedReport> {
+    let mut tokens = line.split_whitespace();
+    let _tag = tokens.next()?;
+    let timestamp = Duration::from_micros(tokens.next()?.parse().ok()?);
+    let event = tokens.next()?;
+    let address = tokens.next()?;
+    let is_in = address.as_bytes().get(1) == Some(&b'i');
+
+    let direction = match (event, is_in) {
+        ("S", false) => Direction::ToDevice,
+        ("C", true) => Direction::FromDevice,
+        _ => return None,
+    };
+
+    let hex: String = tokens.skip_while(|t| *t != "=").skip(1).collect();
+    let data = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    report(timestamp, direction, &data)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let b: [u8; 2] = self.slice(offset, 2)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let b: [u8; 4] = self.slice(offset, 4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn slice(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.bytes
+            .get(offset..offset + len)
+            .ok_or_else(|| MadRError::InvalidCapture("Truncated capture".into()))
+    }
+}
+
+fn parse_pcap(bytes: &[u8]) -> Result<Vec<CapturedReport>> {
+    let big_endian = bytes[0] == 0xA1;
+    let nanos = bytes[2..4] == [0x3C, 0x4D] || bytes[0..2] == [0x4D, 0x3C];
+    let r = Reader { bytes, big_endian };
+    let link_type = r.u32(20)?;
+
+    let mut reports = Vec::new();
+    let mut offset = 24;
+    while offset < bytes.len() {
+        let seconds = r.u32(offset)? as u64;
+        let fraction = r.u32(offset + 4)? as u64;
+        let captured = r.u32(offset + 8)? as usize;
+        let data = r.slice(offset + 16, captured)?;
+
+        let timestamp = if nanos {
+            Duration::from_secs(seconds) + Duration::from_nanos(fraction)
+        } else {
+            Duration::from_secs(seconds) + Duration::from_micros(fraction)
+        };
+        reports.extend(parse_usbmon_packet(link_type, timestamp, data));
+        offset += 16 + captured;
+    }
+
+    Ok(reports)
+}
+
+fn parse_pcapng(bytes: &[u8]) -> Result<Vec<CapturedReport>> {
+    let mut reports = Vec::new();
+    let mut link_types = Vec::new();
+    let mut r = Reader {
+        bytes,
+        big_endian: false,
+    };
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        r.slice(offset, 12)?;
+
+        let block_type = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        if block_type == 0x0A0D0D0A {
+            // Section header: the byte order magic decides endianness for the whole section
+            r.big_endian = bytes.get(offset + 8..offset + 12) == Some(&[0x1A, 0x2B, 0x3C, 0x4D]);
+            link_types.clear();
+        }
+
+        let block_len = r.u32(offset + 4)? as usize;
+        if block_len < 12 {
+            return Err(MadRError::InvalidCapture("Invalid pcapng block".into()));
+        }
+        r.slice(offset, block_len)?;
+
+        match r.u32(offset)? {
+            0x00000001 => link_types.push(r.u16(offset + 8)? as u32),
+            0x00000006 => {
+                let interface = r.u32(offset + 8)? as usize;
+                let ts_high = r.u32(offset + 12)? as u64;
+                let ts_low = r.u32(offset + 16)? as u64;
+                let captured = r.u32(offset + 20)? as usize;
+                let data = r.slice(offset + 28, captured)?;
+
+                // Interfaces are assumed to use the default microsecond resolution
+                let timestamp = Duration::from_micros((ts_high << 32) | ts_low);
+                if let Some(link_type) = link_types.get(interface) {
+                    reports.extend(parse_usbmon_packet(*link_type, timestamp, data));
+                }
+            }
+            _ => {}
+        }
+
+        offset += block_len;
+    }
+
+    Ok(reports)
+}
+
+// Binary usbmon header, 48 bytes for LINKTYPE_USB_LINUX and 64 for the mmapped variant
+fn parse_usbmon_packet(link_type: u32, timestamp: Duration, data: &[u8]) -> Option<CapturedReport> {
+    let header_len = match link_type {
+        LINKTYPE_USB_LINUX => 48,
+        LINKTYPE_USB_LINUX_MMAPPED => 64,
+        _ => return None,
+    };
+
+    let header = data.get(..header_len)?;
+    let is_in = header[10] & 0x80 != 0;
+    let has_data = header[15] == 0;
+
+    let direction = match (header[8], is_in) {
+        (b'S', false) => Direction::ToDevice,
+        (b'C', true) => Direction::FromDevice,
+        _ => return None,
+    };
+
+    if !has_data {
+        return None;
+    }
+
+    report(timestamp, direction, &data[header_len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Packet;
+
+    fn request() -> Vec<u8> {
+        Packet::read(0xA9, 2).encode().to_vec()
+    }
+
+    fn response() -> Vec<u8> {
+        Packet::write_fields(0xA9, &[&[0x08]]).encode().to_vec()
+    }
+
+    // usbmon header for a SET_REPORT going out or an interrupt IN coming back
+    fn usbmon(link_type: u32, outgoing: bool, data: &[u8]) -> Vec<u8> {
+        let mut header = vec![0u8; 64];
+        header[8] = if outgoing { b'S' } else { b'C' };
+        header[10] = if outgoing { 0x00 } else { 0x82 };
+        header[14] = if outgoing { 0 } else { b'-' };
+        header.truncate(if link_type == LINKTYPE_USB_LINUX {
+            48
+        } else {
+            64
+        });
+        header.extend_from_slice(data);
+        header
+    }
+
+    fn u16s(value: u16, big_endian: bool) -> [u8; 2] {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn u32s(value: u32, big_endian: bool) -> [u8; 4] {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    // (seconds, fraction, outgoing, report) per packet
+    fn pcap(
+        big_endian: bool,
+        nanos: bool,
+        link_type: u32,
+        packets: &[(u32, u32, bool)],
+    ) -> Vec<u8> {
+        let magic = if nanos { 0xA1B23C4D } else { 0xA1B2C3D4 };
+        let mut bytes = u32s(magic, big_endian).to_vec();
+        bytes.extend_from_slice(&u16s(2, big_endian));
+        bytes.extend_from_slice(&u16s(4, big_endian));
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&u32s(0xFFFF, big_endian));
+        bytes.extend_from_slice(&u32s(link_type, big_endian));
+
+        for &(seconds, fraction, outgoing) in packets {
+            let report = if outgoing { request() } else { response() };
+            let data = usbmon(link_type, outgoing, &report);
+            bytes.extend_from_slice(&u32s(seconds, big_endian));
+            bytes.extend_from_slice(&u32s(fraction, big_endian));
+            bytes.extend_from_slice(&u32s(data.len() as u32, big_endian));
+            bytes.extend_from_slice(&u32s(data.len() as u32, big_endian));
+            bytes.extend_from_slice(&data);
+        }
+
+        bytes
+    }
+
+    fn pcapng_block(block_type: u32, body: &[u8], big_endian: bool) -> Vec<u8> {
+        let padding = (4 - body.len() % 4) % 4;
+        let total_len = (12 + body.len() + padding) as u32;
+
+        let mut block = u32s(block_type, big_endian).to_vec();
+        block.extend_from_slice(&u32s(total_len, big_endian));
+        block.extend_from_slice(body);
+        block.extend_from_slice(&[0; 3][..padding]);
+        block.extend_from_slice(&u32s(total_len, big_endian));
+        block
+    }
+
+    // (microseconds, outgoing) per packet
+    fn pcapng(big_endian: bool, packets: &[(u64, bool)]) -> Vec<u8> {
+        let mut shb = u32s(0x1A2B3C4D, big_endian).to_vec();
+        shb.extend_from_slice(&u16s(1, big_endian));
+        shb.extend_from_slice(&u16s(0, big_endian));
+        shb.extend_from_slice(&[0xFF; 8]);
+        let mut bytes = pcapng_block(0x0A0D0D0A, &shb, big_endian);
+
+        let mut idb = u16s(LINKTYPE_USB_LINUX_MMAPPED as u16, big_endian).to_vec();
+        idb.extend_from_slice(&[0; 6]);
+        bytes.extend(pcapng_block(0x00000001, &idb, big_endian));
+
+        for &(micros, outgoing) in packets {
+            let report = if outgoing { request() } else { response() };
+            let data = usbmon(LINKTYPE_USB_LINUX_MMAPPED, outgoing, &report);
+            let mut epb = u32s(0, big_endian).to_vec();
+            epb.extend_from_slice(&u32s((micros >> 32) as u32, big_endian));
+            epb.extend_from_slice(&u32s(micros as u32, big_endian));
+            epb.extend_from_slice(&u32s(data.len() as u32, big_endian));
+            epb.extend_from_slice(&u32s(data.len() as u32, big_endian));
+            epb.extend_from_slice(&data);
+            bytes.extend(pcapng_block(0x00000006, &epb, big_endian));
+        }
+
+        bytes
+    }
+
+    fn assert_request_and_response(reports: &[CapturedReport], gap: Duration) {
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].timestamp(), Duration::ZERO);
+        assert_eq!(reports[0].direction(), Direction::ToDevice);
+        assert_eq!(reports[0].data(), request());
+        assert_eq!(reports[1].timestamp(), gap);
+        assert_eq!(reports[1].direction(), Direction::FromDevice);
+        assert_eq!(reports[1].data(), response());
+    }
+
+    // usbmon text groups data in 4-byte words
+    fn usbmon_words(bytes: &[u8]) -> String {
+        bytes
+            .chunks(4)
+            .map(|word| {
+                word.iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn parses_usbmon_text() {
+        let text = format!(
+            "ffff8881 3575914555 S Co:1:005:0 s 21 09 0308 0001 0011 17 = {}\n\
+             ffff8881 3575914600 C Co:1:005:0 0 17 >\n\
+             ffff8881 3575914555 S Ii:1:005:2 -115:1 64 <\n\
+             ffff8881 3575915555 C Ii:1:005:2 0 17 = {}\n\
+             ffff8881 3575916000 C Ii:1:005:2 0 8 = 01020304 05060708\n\
+             ffff8881 3575917000 C Ii:1:005:2 0 17 = 0807zz\n\
+             garbage\n",
+            usbmon_words(&request()),
+            usbmon_words(&response())
+        );
+
+        let reports = parse(text.as_bytes()).unwrap();
+
+        assert_request_and_response(&reports, Duration::from_micros(1000));
+        assert!(reports[0].describe().starts_with("read sleep/debounce"));
+    }
+
+    #[test]
+    fn parses_trace_text() {
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| format!(" {:02x}", b))
+                .collect::<String>()
+        };
+        let text = format!(
+            "1.5 > output{}\n1.5 < input\n1.75 < input{}\n",
+            hex(&request()),
+            hex(&response())
+        );
+
+        assert_request_and_response(&parse(text.as_bytes()).unwrap(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn parses_pcap_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            for link_type in [LINKTYPE_USB_LINUX, LINKTYPE_USB_LINUX_MMAPPED] {
+                let bytes = pcap(
+                    big_endian,
+                    false,
+                    link_type,
+                    &[(10, 999_000, true), (11, 500, false)],
+                );
+                assert_request_and_response(&parse(&bytes).unwrap(), Duration::from_micros(1500));
+            }
+
+            let bytes = pcap(
+                big_endian,
+                true,
+                LINKTYPE_USB_LINUX,
+                &[(10, 5, true), (10, 25, false)],
+            );
+            assert_request_and_response(&parse(&bytes).unwrap(), Duration::from_nanos(20));
+        }
+    }
+
+    #[test]
+    fn parses_pcapng_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            let bytes = pcapng(big_endian, &[(1 << 33, true), ((1 << 33) + 700, false)]);
+            assert_request_and_response(&parse(&bytes).unwrap(), Duration::from_micros(700));
+        }
+    }
+
+    #[test]
+    fn skips_other_traffic() {
+        // Another link type, then a report with the wrong ID
+        let bytes = pcap(false, false, 1, &[(0, 0, true)]);
+        assert!(parse(&bytes).unwrap().is_empty());
+
+        let mut bytes = pcapng(false, &[(0, true), (5, false)]);
+        let id = bytes.len() - 4 - 3 - 17;
+        bytes[id] = 0x09;
+        let reports = parse(&bytes).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].direction(), Direction::ToDevice);
+    }
+
+    #[test]
+    fn truncated_captures_are_rejected() {
+        let pcap = pcap(
+            false,
+            false,
+            LINKTYPE_USB_LINUX,
+            &[(0, 0, true), (0, 5, false)],
+        );
+        let pcapng = pcapng(true, &[(0, true), (5, false)]);
+
+        for bytes in [&pcap, &pcapng] {
+            for len in [bytes.len() - 1, bytes.len() - 30, 22, 10] {
+                assert!(
+                    matches!(parse(&bytes[..len]), Err(MadRError::InvalidCapture(_))),
+                    "{} of {} bytes",
+                    len,
+                    bytes.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_captures_are_rejected() {
+        let mut bytes = pcapng(false, &[(0, true)]);
+        let epb = bytes.len() - 4 - 3 - 17 - 64 - 28;
+        bytes[epb + 4..epb + 8].copy_from_slice(&8u32.to_le_bytes());
+        assert!(matches!(parse(&bytes), Err(MadRError::InvalidCapture(_))));
+
+        let bytes = [0xD4, 0xC3, 0xB2, 0xA1, 0xFF, 0xFE];
+        assert!(matches!(parse(&bytes), Err(MadRError::InvalidCapture(_))));
+
+        assert!(matches!(
+            parse(&[0xFF, 0xFE, 0x00, 0x80]),
+            Err(MadRError::InvalidCapture(_))
+        ));
+        assert!(parse(b"0.1 > control 08").is_err());
+    }
+}
@@ -1,5 +1,10 @@
+// Debounce shares the block at 0xA9 with the sleep timeout (see sleep.rs):
+// 0xA9 debounce, 0xAB motion sync, 0xAD sleep timeout, 0xAF angle snapping and
+// 0xB1 ripple control.
+
 use crate::Result;
 use crate::device::Device;
+use crate::packet;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Debounce {
@@ -33,33 +38,18 @@ impl TryFrom<u8> for Debounce {
     }
 }
 
-fn get_debounce_report(debounce: Debounce) -> Vec<u8> {
-    let debounce_ms = debounce as u8;
-    vec![
-        0x08,
-        0x07,
-        0x00,
-        0x00,
-        0xA9,
-        0x0A,
-        debounce_ms,
-        0x55u8.wrapping_sub(debounce_ms),
-        0x01,
-        0x54,
-        0x06,
-        0x4F,
-        0x00,
-        0x55,
-        0x00,
-        0x55,
-        0xEA,
-    ]
+impl Debounce {
+    /// Read debounce time from device
+    pub fn read(device: &Device) -> Result<Self> {
+        let block = device.read_block(0xA9, 2)?;
+        let fields = packet::fields(&block, 2)?;
+
+        Self::try_from(fields[0][0])
+    }
 }
 
-/// Apply debounce time
+/// Apply debounce time, keeping the other settings in its block.
+/// The report used to write the whole block, resetting the sleep timeout to one minute.
 pub fn apply_setting(device: &Device, debounce: Debounce) -> Result<()> {
-    let report = get_debounce_report(debounce);
-    device.send_feature_report(&report)?;
-
-    Ok(())
+    device.update_settings(0xA9, 10, |values| values[0] = debounce as u8)
 }
@@ -1,53 +1,853 @@
+use std::fmt;
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::packet::{self, Command, PACKET_LEN, Packet};
+use crate::transport::{HidTransport, Transport};
 use crate::{MadRError, Result};
-use hidapi::{HidApi, HidDevice};
+use hidapi::{DeviceInfo, HidApi, HidError};
 
 const VXE_VID: u16 = 0x373b;
 const MADR_WIRED_PID: u16 = 0x103f;
 const MADR_WIRELESS_PID: u16 = 0x1040;
 
-#[derive(Debug)]
+/// Default minimum time between two packets sent to the mouse.
+/// Back-to-back packets are sometimes dropped by the firmware.
+pub const DEFAULT_PACKET_INTERVAL: Duration = Duration::from_millis(50);
+
+// Upper bound on stale reports discarded before a request, in case the mouse keeps sending
+const MAX_DRAINED_REPORTS: usize = 64;
+// Reads of the DPI and RGB tables go out as feature reports (see `Device::request`)
+const STAGE_TABLES: std::ops::Range<u8> = 0x0C..0x4C;
+// Block transfers are split into 8-byte packets so 4-byte DPI/RGB entries are never cut in half
+const BLOCK_CHUNK: usize = 8;
+
+fn is_madr_interface(info: &DeviceInfo) -> bool {
+    info.vendor_id() == VXE_VID
+        && (info.product_id() == MADR_WIRED_PID || info.product_id() == MADR_WIRELESS_PID)
+        && info.interface_number() == 1
+}
+
+/// How [`Device`] waits for responses to read requests.
+///
+/// Each attempt drains stale input reports, sends the request, then reads until a response
+/// echoing the request arrives or `timeout` runs out. Failed attempts are retried after
+/// `backoff`, growing linearly with every retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    timeout: Duration,
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(50),
+            attempts: 3,
+            backoff: Duration::from_millis(50),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(timeout: Duration, attempts: u32, backoff: Duration) -> Self {
+        Self {
+            timeout,
+            attempts: attempts.max(1),
+            backoff,
+        }
+    }
+
+    /// How long to wait for a matching response on each attempt
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Total number of tries, at least 1
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Delay before the first retry, added again for every later one
+    pub fn backoff(&self) -> Duration {
+        self.backoff
+    }
+}
+
+/// Result of [`Device::probe`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The mouse answered
+    Connected,
+    /// The wireless dongle is present but the mouse didn't answer
+    Asleep,
+    /// A wired mouse didn't answer
+    Unresponsive,
+}
+
+/// A connected MAD R mouse or wireless dongle, as returned by [`Device::list`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    path: String,
+    serial: Option<String>,
+    product: Option<String>,
+    wired: bool,
+}
+
+impl DeviceDescriptor {
+    fn from_info(info: &DeviceInfo) -> Self {
+        Self {
+            path: info.path().to_string_lossy().into_owned(),
+            serial: info.serial_number().map(String::from),
+            product: info.product_string().map(String::from),
+            wired: info.product_id() == MADR_WIRED_PID,
+        }
+    }
+
+    /// Platform-specific HID path, usable with [`Device::open_path`]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// USB serial number, if the device reports one
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// USB product string, if the device reports one
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    pub fn is_wired(&self) -> bool {
+        self.wired
+    }
+}
+
+/// A connected mouse. Every exchange with it holds a lock on the transport, so threads
+/// sharing one `Device` never interleave reports: a request always gets its own response,
+/// and a single block read, block write or read-modify-write of one settings block is
+/// never split.
+///
+/// Operations made of several of those take the lock once per step, so they can interleave
+/// with other threads. That includes [`DpiTable::write`](crate::dpi::DpiTable::write),
+/// [`apply_dpi_setting`](crate::dpi::apply_dpi_setting) and changing the stage count or
+/// active stage, which read the performance block and write it back separately.
 pub struct Device {
     wired: bool,
-    hid: HidDevice,
+    link: Mutex<Link>,
+    packet_interval: Duration,
+    retry_policy: RetryPolicy,
+}
+
+// The transport and the pacing state that goes with it
+struct Link {
+    transport: Box<dyn Transport>,
+    last_sent: Option<Instant>,
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Device")
+            .field("wired", &self.wired)
+            .field("packet_interval", &self.packet_interval)
+            .field("retry_policy", &self.retry_policy)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Device {
+    /// List every connected MAD R device
+    pub fn list() -> Result<Vec<DeviceDescriptor>> {
+        let api = HidApi::new()?;
+
+        Ok(api
+            .device_list()
+            .filter(|x| is_madr_interface(x))
+            .map(DeviceDescriptor::from_info)
+            .collect())
+    }
+
+    /// Open the first MAD R device found
     pub fn open() -> Result<Self> {
+        Self::open_first(|_| true)
+    }
+
+    /// Open the MAD R device at the given HID path
+    pub fn open_path(path: &str) -> Result<Self> {
+        Self::open_first(|x| x.path().to_string_lossy() == path)
+    }
+
+    /// Open the MAD R device with the given serial number
+    pub fn open_serial(serial: &str) -> Result<Self> {
+        Self::open_first(|x| x.serial_number() == Some(serial))
+    }
+
+    fn open_first(predicate: impl Fn(&DeviceInfo) -> bool) -> Result<Self> {
         let api = HidApi::new()?;
 
-        let device_info = api.device_list().find(|x| {
-            x.vendor_id() == VXE_VID
-                && (x.product_id() == MADR_WIRED_PID || x.product_id() == MADR_WIRELESS_PID)
-                && x.interface_number() == 1
-        });
+        let device_info = api
+            .device_list()
+            .find(|x| is_madr_interface(x) && predicate(x));
 
         if let Some(device_info) = device_info {
-            let device = device_info.open_device(&api)?;
-            return Ok(Device {
-                wired: device_info.product_id() == MADR_WIRED_PID,
-                hid: device,
-            });
+            // Only some hidapi backends report the OS error, the default one gives just a
+            // message, so anything else is a generic failure that still mentions permissions
+            let path = device_info.path().to_string_lossy().into_owned();
+            let device = device_info.open_device(&api).map_err(|e| match e {
+                HidError::IoError { error } if error.kind() == io::ErrorKind::PermissionDenied => {
+                    MadRError::PermissionDenied(path)
+                }
+                source => MadRError::OpenFailed { path, source },
+            })?;
+            return Ok(Device::from_transport(
+                HidTransport::new(device),
+                device_info.product_id() == MADR_WIRED_PID,
+            ));
         }
 
         Err(MadRError::DeviceNotFound)
     }
 
+    /// Build a device on top of any [`Transport`], e.g. a mock or an alternate HID backend
+    pub fn from_transport(transport: impl Transport + 'static, wired: bool) -> Self {
+        Device {
+            wired,
+            link: Mutex::new(Link {
+                transport: Box::new(transport),
+                last_sent: None,
+            }),
+            packet_interval: DEFAULT_PACKET_INTERVAL,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Replace the transport with one wrapping it, e.g. a [`TraceTransport`](crate::trace::TraceTransport)
+    pub fn wrap_transport<T: Transport + 'static>(
+        self,
+        wrap: impl FnOnce(Box<dyn Transport>) -> T,
+    ) -> Self {
+        let link = self.link.into_inner().unwrap_or_else(|e| e.into_inner());
+        Device {
+            link: Mutex::new(Link {
+                transport: Box::new(wrap(link.transport)),
+                ..link
+            }),
+            ..self
+        }
+    }
+
     pub fn is_wired(&self) -> bool {
         self.wired
     }
 
-    pub(crate) fn send_feature_report(&self, report: &[u8]) -> Result<()> {
-        self.hid.send_feature_report(report)?;
+    /// Check whether the mouse answers requests. A wireless dongle stays connected
+    /// while the mouse sleeps, so this is how to tell the two apart.
+    pub fn probe(&self) -> Result<ConnectionState> {
+        match self.request(&Packet::battery()) {
+            Ok(_) => Ok(ConnectionState::Connected),
+            Err(MadRError::MouseAsleep) => Ok(ConnectionState::Asleep),
+            Err(MadRError::NoResponse) => Ok(ConnectionState::Unresponsive),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Minimum time between two packets sent to the mouse
+    pub fn packet_interval(&self) -> Duration {
+        self.packet_interval
+    }
+
+    /// Change the minimum time between packets, e.g. to zero for a simulated or replayed device
+    pub fn set_packet_interval(&mut self, interval: Duration) {
+        self.packet_interval = interval;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Read `len` bytes of configuration memory starting at `address`.
+    /// The result is raw memory, so every value is followed by its check byte.
+    pub fn read_block(&self, address: u8, len: usize) -> Result<Vec<u8>> {
+        self.read_locked(&mut self.link(), address, len)
+    }
+
+    /// Write raw configuration memory starting at `address`.
+    /// Packet checksums are added automatically, but `bytes` must carry its own check bytes
+    /// (see [`packet::checksum`](crate::packet::checksum)) or the firmware will drop the write.
+    pub fn write_block(&self, address: u8, bytes: &[u8]) -> Result<()> {
+        check_block_range(address, bytes.len())?;
+
+        let mut link = self.link();
+        for (i, chunk) in bytes.chunks(BLOCK_CHUNK).enumerate() {
+            let offset = (i * BLOCK_CHUNK) as u8;
+            self.transmit(&mut link, &Packet::write(address + offset, chunk.to_vec()))?;
+        }
+
         Ok(())
     }
 
-    pub(crate) fn write(&self, data: &[u8]) -> Result<usize> {
-        let size = self.hid.write(data)?;
-        Ok(size)
+    /// Write values starting at `address`, stored as fields of `width` bytes: `width - 1`
+    /// value bytes followed by the check byte, which is added here. The counterpart of
+    /// [`packet::fields`](crate::packet::fields) on a [`Device::read_block`] result.
+    pub fn write_fields(&self, address: u8, width: usize, values: &[u8]) -> Result<()> {
+        if width < 2 || !values.len().is_multiple_of(width - 1) {
+            return Err(MadRError::InvalidPacket(format!(
+                "{} bytes do not split into {}-byte values",
+                values.len(),
+                width.saturating_sub(1)
+            )));
+        }
+
+        let bytes: Vec<u8> = values
+            .chunks(width - 1)
+            .flat_map(|value| value.iter().copied().chain([packet::checksum(value)]))
+            .collect();
+
+        self.write_block(address, &bytes)
     }
 
-    pub(crate) fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
-        let size = self.hid.read_timeout(buf, timeout_ms)?;
-        Ok(size)
+    fn read_locked(&self, link: &mut Link, address: u8, len: usize) -> Result<Vec<u8>> {
+        check_block_range(address, len)?;
+
+        let mut bytes = Vec::with_capacity(len);
+        for offset in (0..len).step_by(BLOCK_CHUNK) {
+            let chunk_len = BLOCK_CHUNK.min(len - offset);
+            let request = Packet::read(address + offset as u8, chunk_len as u8);
+            let response = self.exchange(link, &request)?;
+
+            if response.payload().len() != chunk_len {
+                return Err(MadRError::InvalidPacket(format!(
+                    "Asked for {} bytes at {:#04x}, got {}",
+                    chunk_len,
+                    request.address(),
+                    response.payload().len()
+                )));
+            }
+
+            bytes.extend_from_slice(response.payload());
+        }
+
+        Ok(bytes)
+    }
+
+    /// Read a block of one-byte settings (value and check byte pairs), let `update` change
+    /// the values and write the whole block back, so settings sharing it are preserved
+    pub(crate) fn update_settings(
+        &self,
+        address: u8,
+        len: usize,
+        update: impl FnOnce(&mut [u8]),
+    ) -> Result<()> {
+        let mut link = self.link();
+        let block = self.read_locked(&mut link, address, len)?;
+        let mut values: Vec<u8> = packet::fields(&block, 2)?.iter().map(|f| f[0]).collect();
+        update(&mut values);
+
+        let fields: Vec<&[u8]> = values.iter().map(std::slice::from_ref).collect();
+        self.transmit(&mut link, &Packet::write_fields(address, &fields))
+    }
+
+    /// Send a packet that expects no response
+    pub(crate) fn send(&self, packet: &Packet) -> Result<()> {
+        self.transmit(&mut self.link(), packet)
+    }
+
+    /// Send a packet and wait for a response echoing its command and address,
+    /// retrying according to the [`RetryPolicy`]
+    pub(crate) fn request(&self, packet: &Packet) -> Result<Packet> {
+        self.exchange(&mut self.link(), packet)
+    }
+
+    // Lock the link for one exchange, or for several that must not be split
+    fn link(&self) -> MutexGuard<'_, Link> {
+        self.link.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn transmit(&self, link: &mut Link, packet: &Packet) -> Result<()> {
+        self.pace(link);
+        let result = link.transport.send_feature_report(&packet.encode());
+        link.last_sent = Some(Instant::now());
+        result
+    }
+
+    fn exchange(&self, link: &mut Link, packet: &Packet) -> Result<Packet> {
+        let policy = self.retry_policy;
+        let mut last_error = None;
+
+        for attempt in 0..policy.attempts {
+            if attempt > 0 {
+                thread::sleep(policy.backoff * attempt);
+            }
+
+            self.send_request(link, packet)?;
+
+            match self.read_response(link, packet, policy.timeout)? {
+                Ok(response) => return Ok(response),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error.unwrap_or(MadRError::NoResponse) {
+            MadRError::NoResponse if !self.wired && self.is_asleep(link, packet)? => {
+                Err(MadRError::MouseAsleep)
+            }
+            e => Err(e),
+        }
+    }
+
+    fn send_request(&self, link: &mut Link, packet: &Packet) -> Result<()> {
+        drain(link)?;
+
+        // The DPI and RGB readers always sent their requests as feature reports and the
+        // others as output reports. Nothing shows the firmware accepts either everywhere,
+        // so each request keeps the transfer type it had before read_block existed.
+        self.pace(link);
+        let report = packet.encode();
+        let sent = if is_stage_table_read(packet) {
+            link.transport.send_feature_report(&report)
+        } else {
+            link.transport.write(&report).map(|_| ())
+        };
+        link.last_sent = Some(Instant::now());
+        sent
+    }
+
+    // The dongle stays connected while the mouse sleeps, so silence can mean either that or
+    // that the mouse ignored `unanswered`. Asking for the battery once more tells them apart.
+    fn is_asleep(&self, link: &mut Link, unanswered: &Packet) -> Result<bool> {
+        if unanswered.command() == Command::Battery {
+            return Ok(true);
+        }
+
+        let battery = Packet::battery();
+        self.send_request(link, &battery)?;
+        Ok(self
+            .read_response(link, &battery, self.retry_policy.timeout)?
+            .is_err())
+    }
+
+    // Read until a report echoing `request` arrives. The outer result is for transport
+    // failures, the inner one for a timeout, carrying the last bad report seen if any.
+    fn read_response(
+        &self,
+        link: &mut Link,
+        request: &Packet,
+        timeout: Duration,
+    ) -> Result<Result<Packet>> {
+        let deadline = Instant::now() + timeout;
+        let mut last_error = MadRError::NoResponse;
+
+        let mut buf = [0u8; PACKET_LEN];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(Err(last_error));
+            }
+
+            let timeout_ms = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+            let size = link.transport.read_timeout(&mut buf, timeout_ms)?;
+            if size == 0 {
+                return Ok(Err(last_error));
+            }
+
+            match Packet::decode(&buf[..size]) {
+                Ok(response)
+                    if response.command() == request.command()
+                        && response.address() == request.address() =>
+                {
+                    return Ok(Ok(response));
+                }
+                Ok(response) => {
+                    last_error = MadRError::InvalidPacket(format!(
+                        "Response {:?} at {:#04x} does not match request {:?} at {:#04x}",
+                        response.command(),
+                        response.address(),
+                        request.command(),
+                        request.address()
+                    ));
+                }
+                Err(e) => last_error = e,
+            }
+        }
+    }
+
+    // Wait until the packet interval has passed since the last packet went out
+    fn pace(&self, link: &Link) {
+        if let Some(last) = link.last_sent {
+            let elapsed = last.elapsed();
+            if elapsed < self.packet_interval {
+                thread::sleep(self.packet_interval - elapsed);
+            }
+        }
+    }
+}
+
+// Discard input reports left over from earlier requests
+fn drain(link: &mut Link) -> Result<()> {
+    let mut buf = [0u8; PACKET_LEN];
+    for _ in 0..MAX_DRAINED_REPORTS {
+        if link.transport.read_timeout(&mut buf, 0)? == 0 {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_stage_table_read(packet: &Packet) -> bool {
+    packet.command() == Command::Read && STAGE_TABLES.contains(&packet.address())
+}
+
+fn check_block_range(address: u8, len: usize) -> Result<()> {
+    if address as usize + len > 0x100 {
+        return Err(MadRError::InvalidPacket(format!(
+            "Block of {} bytes at {:#04x} runs past the end of memory",
+            len, address
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::packet::{self, checksum};
+    use crate::sim::SimulatedMouse;
+    use crate::trace::{self, ReportKind, TraceFormat, TraceTransport};
+
+    #[derive(Default)]
+    struct Faults {
+        // Input reports waiting before anything the mouse sends
+        stale: VecDeque<Vec<u8>>,
+        // Reports queued ahead of the mouse's response to the next request
+        before_response: Vec<Vec<u8>>,
+        // Number of upcoming responses to lose
+        lost_responses: usize,
+        requests: usize,
+    }
+
+    // Simulated mouse behind a link that delivers stale, foreign or no responses
+    #[derive(Clone)]
+    struct FaultyLink {
+        mouse: SimulatedMouse,
+        faults: Arc<Mutex<Faults>>,
+    }
+
+    impl FaultyLink {
+        fn device() -> (Self, Device) {
+            Self::connect(true)
+        }
+
+        // Behind a wireless dongle, where silence may mean the mouse is asleep
+        fn wireless() -> (Self, Device) {
+            Self::connect(false)
+        }
+
+        fn connect(wired: bool) -> (Self, Device) {
+            let link = Self {
+                mouse: SimulatedMouse::new(),
+                faults: Arc::default(),
+            };
+            let mut device = Device::from_transport(link.clone(), wired);
+            device.set_packet_interval(Duration::ZERO);
+            device.set_retry_policy(RetryPolicy::new(
+                Duration::from_millis(5),
+                3,
+                Duration::ZERO,
+            ));
+            (link, device)
+        }
+
+        fn faults(&self) -> std::sync::MutexGuard<'_, Faults> {
+            self.faults.lock().unwrap()
+        }
+    }
+
+    impl Transport for FaultyLink {
+        fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+            self.write(report).map(|_| ())
+        }
+
+        fn write(&self, data: &[u8]) -> Result<usize> {
+            self.mouse.write(data)?;
+
+            let mut faults = self.faults();
+            faults.requests += 1;
+            let injected = std::mem::take(&mut faults.before_response);
+            faults.stale.extend(injected);
+
+            if faults.lost_responses > 0 {
+                faults.lost_responses -= 1;
+                self.mouse.read_timeout(&mut [0; PACKET_LEN], 0)?;
+            }
+
+            Ok(data.len())
+        }
+
+        fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+            if let Some(report) = self.faults().stale.pop_front() {
+                buf[..report.len()].copy_from_slice(&report);
+                return Ok(report.len());
+            }
+            self.mouse.read_timeout(buf, timeout_ms)
+        }
+    }
+
+    // A response to reading `address` carrying `value` as a one-byte setting
+    fn response(address: u8, value: u8) -> Vec<u8> {
+        let mut report = Packet::write_fields(address, &[&[value]]).encode();
+        report[1] = Command::Read as u8;
+        report[PACKET_LEN - 1] = checksum(&report[..PACKET_LEN - 1]);
+        report.to_vec()
+    }
+
+    // Text trace output that stays readable after the transport takes it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stage_tables_are_read_with_feature_reports() {
+        let (_, device) = SimulatedMouse::device();
+        let out = SharedBuffer::default();
+        let device =
+            device.wrap_transport(|t| TraceTransport::new(t, out.clone(), TraceFormat::Text));
+
+        device.read_block(0x0C, 8).unwrap();
+        device.read_block(0x44, 8).unwrap();
+        device.read_block(0x00, 6).unwrap();
+        device.read_block(0xB5, 6).unwrap();
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let requests: Vec<ReportKind> = trace::parse_text(&text)
+            .unwrap()
+            .iter()
+            .map(|e| e.kind())
+            .filter(ReportKind::is_outgoing)
+            .collect();
+
+        assert_eq!(
+            requests,
+            [
+                ReportKind::Feature,
+                ReportKind::Feature,
+                ReportKind::Output,
+                ReportKind::Output
+            ]
+        );
+    }
+
+    #[test]
+    fn block_round_trips_across_packets() {
+        let (mouse, device) = SimulatedMouse::device();
+
+        // Four DPI entries, split over two packets
+        let mut bytes = Vec::new();
+        for i in 0..4u8 {
+            let entry = [i, i, 0x00];
+            bytes.extend_from_slice(&entry);
+            bytes.push(checksum(&entry));
+        }
+
+        device.write_block(0x0C, &bytes).unwrap();
+
+        assert_eq!(device.read_block(0x0C, bytes.len()).unwrap(), bytes);
+        assert_eq!(mouse.memory(0x0C, bytes.len()), bytes);
+        assert_eq!(mouse.rejected_reports(), 0);
+    }
+
+    #[test]
+    fn fields_are_written_with_their_check_bytes() {
+        let (mouse, device) = SimulatedMouse::device();
+
+        device
+            .write_fields(0x0C, 4, &[1, 1, 0, 2, 2, 0, 3, 3, 0])
+            .unwrap();
+        device.write_fields(0xA9, 2, &[0x08, 0x01]).unwrap();
+
+        let table = device.read_block(0x0C, 12).unwrap();
+        assert_eq!(
+            packet::fields(&table, 4).unwrap(),
+            [[1, 1, 0], [2, 2, 0], [3, 3, 0]]
+        );
+        assert_eq!(
+            mouse.memory(0xA9, 4),
+            [0x08, checksum(&[0x08]), 0x01, checksum(&[0x01])]
+        );
+        assert_eq!(mouse.rejected_reports(), 0);
+
+        assert!(device.write_fields(0xA9, 4, &[0x08, 0x01]).is_err());
+        assert!(device.write_fields(0xA9, 1, &[0x08]).is_err());
+    }
+
+    #[test]
+    fn write_without_check_bytes_is_dropped() {
+        let (mouse, device) = SimulatedMouse::device();
+        let before = mouse.memory(0xA9, 2);
+
+        device.write_block(0xA9, &[0x02, 0x00]).unwrap();
+
+        assert_eq!(device.read_block(0xA9, 2).unwrap(), before);
+        assert_eq!(mouse.rejected_reports(), 1);
+    }
+
+    #[test]
+    fn block_past_end_of_memory_is_refused() {
+        let (_, device) = SimulatedMouse::device();
+
+        assert!(device.read_block(0xFC, 8).is_err());
+        assert!(device.write_block(0xFE, &[0x00, 0x55, 0x00, 0x55]).is_err());
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let (mouse, device) = SimulatedMouse::device();
+        let device = Arc::new(device);
+
+        // Each thread owns one value in the 0xA9 block and rewrites it over and over.
+        // Interleaved read-modify-writes would put back another thread's stale values.
+        let threads: Vec<_> = (0..5)
+            .map(|field| {
+                let device = Arc::clone(&device);
+                std::thread::spawn(move || {
+                    for n in 1..=20u8 {
+                        device
+                            .update_settings(0xA9, 10, |values| values[field] = n)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let block = mouse.memory(0xA9, 10);
+        for field in block.chunks(2) {
+            assert_eq!(field, [20, checksum(&[20])]);
+        }
+        assert_eq!(mouse.rejected_reports(), 0);
+    }
+
+    #[test]
+    fn stale_reports_are_drained_before_a_request() {
+        let (link, device) = FaultyLink::device();
+        let expected = link.mouse.memory(0xA9, 2);
+
+        // Left over from an earlier read of the same address, so its echo would match
+        link.faults()
+            .stale
+            .extend([response(0xA9, 0x14), response(0x00, 0x02)]);
+
+        assert_eq!(device.read_block(0xA9, 2).unwrap(), expected);
+        assert_eq!(link.faults().requests, 1);
+    }
+
+    #[test]
+    fn responses_to_other_requests_are_skipped() {
+        let (link, device) = FaultyLink::device();
+        let expected = link.mouse.memory(0xA9, 2);
+
+        let mut garbled = response(0xA9, 0x14);
+        garbled[PACKET_LEN - 1] ^= 0xFF;
+        link.faults().before_response = vec![response(0xB5, 0x01), garbled, vec![0x01, 0x02]];
+
+        assert_eq!(device.read_block(0xA9, 2).unwrap(), expected);
+        assert_eq!(link.faults().requests, 1);
+    }
+
+    #[test]
+    fn mismatched_echo_is_not_taken_as_the_response() {
+        let (link, device) = FaultyLink::device();
+        let expected = link.mouse.memory(0xA9, 2);
+
+        // The first attempt only gets an answer for another address of the same length
+        {
+            let mut faults = link.faults();
+            faults.lost_responses = 1;
+            faults.before_response = vec![response(0xB5, 0x01)];
+        }
+
+        assert_eq!(device.read_block(0xA9, 2).unwrap(), expected);
+        assert_eq!(link.faults().requests, 2);
+    }
+
+    #[test]
+    fn request_is_retried_after_a_timeout() {
+        let (link, device) = FaultyLink::device();
+        let expected = link.mouse.memory(0xA9, 2);
+        link.faults().lost_responses = 2;
+
+        assert_eq!(device.read_block(0xA9, 2).unwrap(), expected);
+        assert_eq!(link.faults().requests, 3);
+
+        link.faults().lost_responses = 3;
+        assert!(matches!(
+            device.read_block(0xA9, 2),
+            Err(MadRError::NoResponse)
+        ));
+        assert_eq!(link.faults().requests, 6);
+    }
+
+    #[test]
+    fn wireless_mouse_that_answers_a_probe_is_not_asleep() {
+        let (link, device) = FaultyLink::wireless();
+        link.faults().lost_responses = 3;
+
+        assert!(matches!(
+            device.read_block(0xA9, 2),
+            Err(MadRError::NoResponse)
+        ));
+        // Three attempts, then the battery probe
+        assert_eq!(link.faults().requests, 4);
+    }
+
+    #[test]
+    fn wireless_mouse_that_misses_the_probe_is_asleep() {
+        let (link, device) = FaultyLink::wireless();
+        link.faults().lost_responses = 4;
+
+        assert!(matches!(
+            device.read_block(0xA9, 2),
+            Err(MadRError::MouseAsleep)
+        ));
+        assert_eq!(link.faults().requests, 4);
+
+        // A battery request is its own probe
+        link.faults().lost_responses = 3;
+        assert_eq!(device.probe().unwrap(), ConnectionState::Asleep);
+        assert_eq!(link.faults().requests, 7);
+    }
+
+    #[test]
+    fn wired_mouse_is_never_asleep() {
+        let (link, device) = FaultyLink::device();
+        link.faults().lost_responses = 3;
+
+        assert_eq!(device.probe().unwrap(), ConnectionState::Unresponsive);
+        assert_eq!(link.faults().requests, 3);
     }
 }
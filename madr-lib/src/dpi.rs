@@ -1,10 +1,28 @@
 // See documentation/dpi-and-rgb-encoding.md for details on encoding
 
+use std::fmt;
 use std::str::FromStr;
 
 use crate::device::Device;
+use crate::packet::{self, Packet};
+use crate::performance::{self, MAX_DPI_STAGES, Performance, PollingRate};
 use crate::{MadRError, Result};
 
+// madr's default stage table, not read from a factory reset mouse: 4 of 8 stages enabled,
+// stage 1 active. Restored by `reset` and used by the simulator.
+pub(crate) const DEFAULT_STAGE_COUNT: u8 = 4;
+pub(crate) const DEFAULT_DPI: [u16; 8] = [400, 800, 1600, 3200, 6400, 10000, 20000, 30000];
+pub(crate) const DEFAULT_RGB: [[u8; 3]; 8] = [
+    [255, 0, 0],
+    [0, 255, 0],
+    [0, 0, 255],
+    [255, 255, 0],
+    [255, 0, 255],
+    [0, 255, 255],
+    [255, 255, 255],
+    [255, 128, 0],
+];
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Rgb {
     r: u8,
@@ -16,6 +34,24 @@ impl Rgb {
     pub fn new(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
+
+    pub fn r(&self) -> u8 {
+        self.r
+    }
+
+    pub fn g(&self) -> u8 {
+        self.g
+    }
+
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}", self.r, self.g, self.b)
+    }
 }
 
 impl FromStr for Rgb {
@@ -44,37 +80,39 @@ impl FromStr for Rgb {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct DpiStage {
+pub struct DpiStage {
     x_dpi: u16,
     y_dpi: u16,
 }
 
 impl DpiStage {
-    fn new(x_dpi: u16, y_dpi: u16) -> Self {
+    pub fn new(x_dpi: u16, y_dpi: u16) -> Self {
         Self { x_dpi, y_dpi }
     }
-}
 
-fn read_dpi_stages(device: &Device, report_index: u8) -> Result<Vec<u8>> {
-    let report_id = 0x04 + (report_index * 0x08);
-
-    let mut request = vec![0u8; 17];
-    request[0] = 0x08;
-    request[1] = 0x08;
-    request[4] = report_id;
-    request[5] = 0x08;
-    request[16] = 0x3Du8.wrapping_sub(report_id);
+    pub fn x_dpi(&self) -> u16 {
+        self.x_dpi
+    }
 
-    device.send_feature_report(&request)?;
+    pub fn y_dpi(&self) -> u16 {
+        self.y_dpi
+    }
+}
 
-    let mut buf = [0u8; 17];
-    device.read_timeout(&mut buf, 20)?;
+impl fmt::Display for DpiStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.x_dpi, self.y_dpi)
+    }
+}
 
-    Ok(buf.to_vec())
+fn read_dpi_stages(device: &Device, report_index: u8) -> Result<Vec<u8>> {
+    let report_id = 0x04 + (report_index * 0x08);
+    device.read_block(report_id, 8)
 }
 
-fn decode_dpi_pair(report: &[u8]) -> (DpiStage, DpiStage) {
-    let decode_dpi = |x_low: u8, y_low: u8, high_container: u8| -> (u16, u16) {
+pub(crate) fn decode_dpi_pair(report: &[u8]) -> Result<(DpiStage, DpiStage)> {
+    let decode_dpi = |field: &[u8]| -> DpiStage {
+        let (x_low, y_low, high_container) = (field[0], field[1], field[2]);
         let x_high = (high_container >> 2) & 0x0F;
         let y_high = (high_container >> 6) & 0x03;
 
@@ -84,52 +122,262 @@ fn decode_dpi_pair(report: &[u8]) -> (DpiStage, DpiStage) {
         let x_dpi = (x_val + 1) * 50;
         let y_dpi = (y_val + 1) * 50;
 
-        (x_dpi, y_dpi)
+        DpiStage::new(x_dpi, y_dpi)
     };
 
-    let (x_dpi_a, y_dpi_a) = decode_dpi(report[6], report[7], report[8]);
-    let (x_dpi_b, y_dpi_b) = decode_dpi(report[10], report[11], report[12]);
-
-    let stage_a = DpiStage::new(x_dpi_a, y_dpi_a);
-    let stage_b = DpiStage::new(x_dpi_b, y_dpi_b);
-
-    (stage_a, stage_b)
+    let fields = packet::fields(report, 4)?;
+    Ok((decode_dpi(fields[0]), decode_dpi(fields[1])))
 }
 
 fn read_rgb_stages(device: &Device, report_index: u8) -> Result<Vec<u8>> {
     let report_id = 0x24 + (report_index * 0x08);
+    device.read_block(report_id, 8)
+}
 
-    let mut request = vec![0u8; 17];
-    request[0] = 0x08;
-    request[1] = 0x08;
-    request[4] = report_id;
-    request[5] = 0x08;
-    request[16] = 0x3Du8.wrapping_sub(report_id);
+pub(crate) fn decode_rgb_pair(response: &[u8]) -> Result<(Rgb, Rgb)> {
+    let decode = |field: &[u8]| -> Rgb {
+        Rgb {
+            r: field[0],
+            g: field[1],
+            b: field[2],
+        }
+    };
 
-    device.send_feature_report(&request)?;
+    let fields = packet::fields(response, 4)?;
+    Ok((decode(fields[0]), decode(fields[1])))
+}
 
-    let mut buf = [0u8; 17];
-    device.read_timeout(&mut buf, 20)?;
+/// DPI and color of a single stage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageSettings {
+    dpi: DpiStage,
+    color: Rgb,
+}
+
+impl StageSettings {
+    pub fn new(dpi: DpiStage, color: Rgb) -> Self {
+        Self { dpi, color }
+    }
 
-    Ok(buf.to_vec())
+    pub fn dpi(&self) -> DpiStage {
+        self.dpi
+    }
+
+    pub fn color(&self) -> &Rgb {
+        &self.color
+    }
 }
 
-fn decode_rgb_pair(response: &[u8]) -> (Rgb, Rgb) {
-    let decode = |offset: usize| -> Rgb {
-        Rgb {
-            r: response[offset],
-            g: response[offset + 1],
-            b: response[offset + 2],
+/// Every slot of the stage table, including the ones past the enabled stage count
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DpiTable {
+    stages: Vec<StageSettings>,
+    stage_count: u8,
+    active_stage: u8,
+}
+
+impl Default for DpiTable {
+    fn default() -> Self {
+        let stages = DEFAULT_DPI
+            .iter()
+            .zip(DEFAULT_RGB)
+            .map(|(dpi, [r, g, b])| {
+                StageSettings::new(DpiStage::new(*dpi, *dpi), Rgb::new(r, g, b))
+            })
+            .collect();
+
+        Self {
+            stages,
+            stage_count: DEFAULT_STAGE_COUNT,
+            active_stage: 1,
         }
-    };
+    }
+}
+
+impl DpiTable {
+    /// Read all stages from device
+    pub fn read(device: &Device) -> Result<Self> {
+        Self::read_with(device, &Performance::read(device)?)
+    }
+
+    // Read the stage slots, taking the stage count and active stage from `performance`
+    fn read_with(device: &Device, performance: &Performance) -> Result<Self> {
+        let mut stages = Vec::with_capacity(MAX_DPI_STAGES as usize);
+        for report_index in 1..=MAX_DPI_STAGES / 2 {
+            let (dpi_a, dpi_b) = decode_dpi_pair(&read_dpi_stages(device, report_index)?)?;
+            let (rgb_a, rgb_b) = decode_rgb_pair(&read_rgb_stages(device, report_index)?)?;
+
+            stages.push(StageSettings::new(dpi_a, rgb_a));
+            stages.push(StageSettings::new(dpi_b, rgb_b));
+        }
+
+        Ok(Self {
+            stages,
+            stage_count: performance.stage_count(),
+            active_stage: performance.dpi_stage(),
+        })
+    }
+
+    /// All stage slots, stage 1 first
+    pub fn stages(&self) -> &[StageSettings] {
+        &self.stages
+    }
+
+    /// Settings of a stage, numbered from 1
+    pub fn stage(&self, stage: u8) -> Option<&StageSettings> {
+        self.stages.get((stage as usize).checked_sub(1)?)
+    }
+
+    /// Number of enabled stages
+    pub fn stage_count(&self) -> u8 {
+        self.stage_count
+    }
+
+    pub fn active_stage(&self) -> u8 {
+        self.active_stage
+    }
+
+    /// Change a stage, numbered from 1. Takes effect on the next [`DpiTable::write`].
+    pub fn set_stage(&mut self, stage: u8, settings: StageSettings) -> Result<()> {
+        if !(1..=MAX_DPI_STAGES).contains(&stage) {
+            return Err(MadRError::InvalidDpi(format!(
+                "DPI stage must be between 1 and {}",
+                MAX_DPI_STAGES
+            )));
+        }
+
+        if !is_valid_dpi(settings.dpi.x_dpi) || !is_valid_dpi(settings.dpi.y_dpi) {
+            return Err(MadRError::InvalidDpi(
+                "DPI must be between 100 and 30000 and a multiple of 50".into(),
+            ));
+        }
+
+        self.stages[stage as usize - 1] = settings;
+        Ok(())
+    }
+
+    /// Change the number of enabled stages. An active stage past the new count moves
+    /// to the last enabled one. Writing a changed count needs the `experimental` feature.
+    pub fn set_stage_count(&mut self, count: u8) -> Result<()> {
+        if !(1..=MAX_DPI_STAGES).contains(&count) {
+            return Err(MadRError::InvalidDpi(format!(
+                "Stage count must be between 1 and {}",
+                MAX_DPI_STAGES
+            )));
+        }
+
+        self.stage_count = count;
+        self.active_stage = self.active_stage.min(count);
+        Ok(())
+    }
+
+    pub fn set_active_stage(&mut self, stage: u8) -> Result<()> {
+        let last = performance::last_selectable_stage(self.stage_count);
+        if !(1..=last).contains(&stage) {
+            return Err(MadRError::InvalidDpi(format!(
+                "DPI stage must be between 1 and {}",
+                last
+            )));
+        }
+
+        self.active_stage = stage;
+        Ok(())
+    }
+
+    /// Write the table to device, only sending the stage pairs that changed, then read it
+    /// back to verify. If anything fails, the table the device had before is restored.
+    /// Other threads writing stages at the same time can interleave with this.
+    pub fn write(&self, device: &Device) -> Result<()> {
+        let performance = Performance::read(device)?;
+
+        #[cfg(not(feature = "experimental"))]
+        if self.stage_count != performance.stage_count() {
+            return Err(MadRError::InvalidDpi(
+                "Changing the number of enabled stages needs the experimental feature".into(),
+            ));
+        }
+
+        let original = Self::read_with(device, &performance)?;
+        let polling_rate = performance.polling_rate();
+
+        let result = self
+            .write_changes(device, &original, polling_rate)
+            .and_then(|_| self.verify(device));
+
+        if let Err(e) = result {
+            // Best effort: the original error is more useful than a failed rollback
+            let _ = original.write_changes(device, self, polling_rate);
+            return Err(e);
+        }
+
+        Ok(())
+    }
 
-    (decode(6), decode(10))
+    // Send the reports turning `current` into this table
+    fn write_changes(
+        &self,
+        device: &Device,
+        current: &DpiTable,
+        polling_rate: PollingRate,
+    ) -> Result<()> {
+        for report_index in 1..=MAX_DPI_STAGES / 2 {
+            let i = (report_index as usize - 1) * 2;
+            let (a, b) = (&self.stages[i], &self.stages[i + 1]);
+            let (old_a, old_b) = (&current.stages[i], &current.stages[i + 1]);
+
+            if (a.dpi, b.dpi) != (old_a.dpi, old_b.dpi) {
+                device.send(&encode_dpi_pair(report_index, &a.dpi, &b.dpi))?;
+            }
+
+            if (&a.color, &b.color) != (&old_a.color, &old_b.color) {
+                device.send(&encode_rgb_pair(report_index, &a.color, &b.color))?;
+            }
+        }
+
+        if (self.stage_count, self.active_stage) != (current.stage_count, current.active_stage) {
+            performance::write_settings(
+                device,
+                &Performance::new(self.active_stage, self.stage_count, polling_rate),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn verify(&self, device: &Device) -> Result<()> {
+        let written = Self::read(device)?;
+
+        if let Some(i) = (0..self.stages.len()).find(|&i| written.stages[i] != self.stages[i]) {
+            let (expected, actual) = (&self.stages[i], &written.stages[i]);
+            return Err(MadRError::VerificationFailed(format!(
+                "stage {} reads back as {} ({}), expected {} ({})",
+                i + 1,
+                actual.dpi,
+                actual.color,
+                expected.dpi,
+                expected.color
+            )));
+        }
+
+        if (written.stage_count, written.active_stage) != (self.stage_count, self.active_stage) {
+            return Err(MadRError::VerificationFailed(format!(
+                "stage {} of {} reads back, expected {} of {}",
+                written.active_stage, written.stage_count, self.active_stage, self.stage_count
+            )));
+        }
+
+        Ok(())
+    }
 }
 
-fn encode_dpi_pair(report_index: u8, stage_a: &DpiStage, stage_b: &DpiStage) -> Vec<u8> {
+fn is_valid_dpi(dpi: u16) -> bool {
+    dpi.is_multiple_of(50) && (100..=30000).contains(&dpi)
+}
+
+fn encode_dpi_pair(report_index: u8, stage_a: &DpiStage, stage_b: &DpiStage) -> Packet {
     let report_id = 0x04 + (report_index * 0x08);
 
-    let encode_dpi = |x: u16, y: u16| -> (u8, u8, u8, u8) {
+    let encode_dpi = |x: u16, y: u16| -> [u8; 3] {
         let x_val = (x / 50).saturating_sub(1);
         let y_val = (y / 50).saturating_sub(1);
 
@@ -141,69 +389,22 @@ fn encode_dpi_pair(report_index: u8, stage_a: &DpiStage, stage_b: &DpiStage) ->
 
         let high_container = (y_high << 6) | (x_high << 2);
 
-        let checksum = 0x55u8
-            .wrapping_sub(x_low)
-            .wrapping_sub(y_low)
-            .wrapping_sub(high_container);
-
-        (x_low, y_low, high_container, checksum)
+        [x_low, y_low, high_container]
     };
 
-    let (x_low_a, y_low_a, high_a, check_a) = encode_dpi(stage_a.x_dpi, stage_a.y_dpi);
-    let (x_low_b, y_low_b, high_b, check_b) = encode_dpi(stage_b.x_dpi, stage_b.y_dpi);
+    let field_a = encode_dpi(stage_a.x_dpi, stage_a.y_dpi);
+    let field_b = encode_dpi(stage_b.x_dpi, stage_b.y_dpi);
 
-    vec![
-        0x08,
-        0x07,
-        0x00,
-        0x00,
-        report_id,
-        0x08,
-        x_low_a,
-        y_low_a,
-        high_a,
-        check_a,
-        x_low_b,
-        y_low_b,
-        high_b,
-        check_b,
-        0x00,
-        0x00,
-        0x94u8.wrapping_sub(report_id),
-    ]
+    Packet::write_fields(report_id, &[&field_a, &field_b])
 }
 
-fn encode_rgb_pair(report_index: u8, rgb_a: &Rgb, rgb_b: &Rgb) -> Vec<u8> {
+fn encode_rgb_pair(report_index: u8, rgb_a: &Rgb, rgb_b: &Rgb) -> Packet {
     let report_id = 0x24 + (report_index * 0x08);
 
-    let checksum_a = 0x55u8
-        .wrapping_sub(rgb_a.r)
-        .wrapping_sub(rgb_a.g)
-        .wrapping_sub(rgb_a.b);
-    let checksum_b = 0x55u8
-        .wrapping_sub(rgb_b.r)
-        .wrapping_sub(rgb_b.g)
-        .wrapping_sub(rgb_b.b);
-
-    vec![
-        0x08,
-        0x07,
-        0x00,
-        0x00,
+    Packet::write_fields(
         report_id,
-        0x08,
-        rgb_a.r,
-        rgb_a.g,
-        rgb_a.b,
-        checksum_a,
-        rgb_b.r,
-        rgb_b.g,
-        rgb_b.b,
-        checksum_b,
-        0x00,
-        0x00,
-        0x94u8.wrapping_sub(report_id),
-    ]
+        &[&[rgb_a.r, rgb_a.g, rgb_a.b], &[rgb_b.r, rgb_b.g, rgb_b.b]],
+    )
 }
 
 pub fn apply_dpi_setting(
@@ -219,17 +420,25 @@ pub fn apply_dpi_setting(
         ));
     }
 
+    let last = performance::last_selectable_stage(Performance::read(device)?.stage_count());
+    if !(1..=last).contains(&stage) {
+        return Err(MadRError::InvalidDpi(format!(
+            "DPI stage must be between 1 and {}",
+            last
+        )));
+    }
+
     let report_index: u8 = (stage as f32 / 2.0).ceil() as u8;
 
     if let Some(x_dpi_val) = x_dpi {
-        if x_dpi_val % 50 != 0 || !(100..=30000).contains(&x_dpi_val) {
+        if !is_valid_dpi(x_dpi_val) {
             return Err(MadRError::InvalidDpi(
                 "X DPI must be between 100 and 30000 and a multiple of 50".into(),
             ));
         }
 
         if let Some(y_dpi_val) = y_dpi
-            && (y_dpi_val % 50 != 0 || !(100..=30000).contains(&y_dpi_val))
+            && !is_valid_dpi(y_dpi_val)
         {
             return Err(MadRError::InvalidDpi(
                 "Y DPI must be between 100 and 30000 and a multiple of 50".into(),
@@ -237,7 +446,7 @@ pub fn apply_dpi_setting(
         }
 
         let dpi_stages = read_dpi_stages(device, report_index)?;
-        let (mut stage_a, mut stage_b) = decode_dpi_pair(&dpi_stages);
+        let (mut stage_a, mut stage_b) = decode_dpi_pair(&dpi_stages)?;
 
         if stage % 2 == 1 {
             stage_a.x_dpi = x_dpi_val;
@@ -248,14 +457,14 @@ pub fn apply_dpi_setting(
         }
 
         let dpi_report = encode_dpi_pair(report_index, &stage_a, &stage_b);
-        device.send_feature_report(&dpi_report)?;
+        device.send(&dpi_report)?;
     };
 
     if let Some(rgb_str) = rgb {
         let parsed = Rgb::from_str(rgb_str)?;
 
         let rgb_stages = read_rgb_stages(device, report_index)?;
-        let (mut rgb_a, mut rgb_b) = decode_rgb_pair(&rgb_stages);
+        let (mut rgb_a, mut rgb_b) = decode_rgb_pair(&rgb_stages)?;
 
         if stage % 2 == 1 {
             rgb_a = parsed;
@@ -264,8 +473,307 @@ pub fn apply_dpi_setting(
         }
 
         let rgb_report = encode_rgb_pair(report_index, &rgb_a, &rgb_b);
-        device.send_feature_report(&rgb_report)?;
+        device.send(&rgb_report)?;
     };
 
     Ok(())
 }
+
+/// Enable one more DPI stage, returning the new stage count.
+/// The added stage keeps whatever DPI and color it was last configured with.
+#[cfg(feature = "experimental")]
+pub fn add_stage(device: &Device) -> Result<u8> {
+    let settings = Performance::read(device)?;
+    if settings.stage_count() >= MAX_DPI_STAGES {
+        return Err(MadRError::InvalidDpi(format!(
+            "All {} DPI stages are already enabled",
+            MAX_DPI_STAGES
+        )));
+    }
+
+    let count = settings.stage_count() + 1;
+    performance::apply_settings(
+        device,
+        &Performance::new(settings.dpi_stage(), count, settings.polling_rate()),
+    )?;
+
+    Ok(count)
+}
+
+/// Disable the last DPI stage, returning the new stage count.
+/// If the removed stage was active, the new last stage becomes active.
+#[cfg(feature = "experimental")]
+pub fn remove_stage(device: &Device) -> Result<u8> {
+    let settings = Performance::read(device)?;
+    if settings.stage_count() <= 1 {
+        return Err(MadRError::InvalidDpi(
+            "At least one DPI stage must stay enabled".into(),
+        ));
+    }
+
+    let count = settings.stage_count() - 1;
+    performance::apply_settings(
+        device,
+        &Performance::new(
+            settings.dpi_stage().min(count),
+            count,
+            settings.polling_rate(),
+        ),
+    )?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::sim::SimulatedMouse;
+    use crate::transport::Transport;
+
+    #[test]
+    fn table_round_trips() {
+        let (mouse, device) = SimulatedMouse::device();
+
+        let mut table = DpiTable::read(&device).unwrap();
+        table
+            .set_stage(
+                2,
+                StageSettings::new(DpiStage::new(1200, 900), Rgb::new(1, 2, 3)),
+            )
+            .unwrap();
+        table
+            .set_stage(
+                8,
+                StageSettings::new(DpiStage::new(26000, 26000), Rgb::new(4, 5, 6)),
+            )
+            .unwrap();
+        #[cfg(feature = "experimental")]
+        table.set_stage_count(6).unwrap();
+        table.set_active_stage(5).unwrap();
+
+        table.write(&device).unwrap();
+
+        assert_eq!(DpiTable::read(&device).unwrap(), table);
+        assert_eq!(mouse.rejected_reports(), 0);
+    }
+
+    #[test]
+    fn apply_dpi_setting_changes_one_stage() {
+        let (_, device) = SimulatedMouse::device();
+        let before = DpiTable::read(&device).unwrap();
+
+        apply_dpi_setting(&device, 3, Some(2000), None, Some("9,8,7")).unwrap();
+
+        let after = DpiTable::read(&device).unwrap();
+        for (i, (old, new)) in before.stages().iter().zip(after.stages()).enumerate() {
+            if i == 2 {
+                assert_eq!(
+                    *new,
+                    StageSettings::new(DpiStage::new(2000, 2000), Rgb::new(9, 8, 7))
+                );
+            } else {
+                assert_eq!(old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn dpi_pair_decodes_high_bits() {
+        // 20000 x 12000 DPI: raw 399 (0x18F) and 239 (0xEF)
+        let field = [0x8F, 0xEF, 0x04];
+        let mut report = field.to_vec();
+        report.push(packet::checksum(&field));
+        report.extend_from_slice(&[0x00, 0x00, 0x00, 0x55]);
+
+        let (a, b) = decode_dpi_pair(&report).unwrap();
+        assert_eq!(a, DpiStage::new(20000, 12000));
+        assert_eq!(b, DpiStage::new(50, 50));
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn stages_are_added_and_removed() {
+        let (_, device) = SimulatedMouse::device();
+        performance::apply_settings(&device, &Performance::new(3, 3, PollingRate::Hz1000)).unwrap();
+
+        assert_eq!(add_stage(&device).unwrap(), 4);
+        assert_eq!(Performance::read(&device).unwrap().stage_count(), 4);
+
+        // Removing the active stage moves the selection to the new last stage
+        assert_eq!(remove_stage(&device).unwrap(), 3);
+        assert_eq!(remove_stage(&device).unwrap(), 2);
+        assert_eq!(remove_stage(&device).unwrap(), 1);
+        assert!(remove_stage(&device).is_err());
+
+        let settings = Performance::read(&device).unwrap();
+        assert_eq!((settings.dpi_stage(), settings.stage_count()), (1, 1));
+
+        for count in 2..=MAX_DPI_STAGES {
+            assert_eq!(add_stage(&device).unwrap(), count);
+        }
+        assert!(add_stage(&device).is_err());
+    }
+
+    // Counts reads of the performance block on the way to a simulated mouse
+    struct CountPerformanceReads {
+        mouse: SimulatedMouse,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Transport for CountPerformanceReads {
+        fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+            self.mouse.send_feature_report(report)
+        }
+
+        fn write(&self, data: &[u8]) -> Result<usize> {
+            if data[1] == 0x08 && data[4] == 0x00 {
+                self.reads.fetch_add(1, Ordering::Relaxed);
+            }
+            self.mouse.write(data)
+        }
+
+        fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+            self.mouse.read_timeout(buf, timeout_ms)
+        }
+    }
+
+    #[test]
+    fn write_reads_performance_once_before_verifying() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut device = Device::from_transport(
+            CountPerformanceReads {
+                mouse: SimulatedMouse::new(),
+                reads: Arc::clone(&reads),
+            },
+            true,
+        );
+        device.set_packet_interval(Duration::ZERO);
+
+        let mut table = DpiTable::read(&device).unwrap();
+        table.set_active_stage(2).unwrap();
+        reads.store(0, Ordering::Relaxed);
+
+        table.write(&device).unwrap();
+
+        // Once for the original table, once to verify
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn active_stage_past_the_count_is_usable() {
+        let (mouse, device) = SimulatedMouse::device();
+
+        // Stage 6 active with 4 stages, as left by the original tool
+        mouse.set_memory(0x04, &[0x05, packet::checksum(&[0x05])]);
+
+        let table = DpiTable::read(&device).unwrap();
+        assert_eq!((table.active_stage(), table.stage_count()), (6, 4));
+        apply_dpi_setting(&device, 2, Some(1200), None, None).unwrap();
+        crate::reset::restore_defaults(&device).unwrap();
+        assert_eq!(DpiTable::read(&device).unwrap(), DpiTable::default());
+    }
+
+    #[cfg(not(feature = "experimental"))]
+    #[test]
+    fn stage_count_changes_are_refused() {
+        let (mouse, device) = SimulatedMouse::device();
+        let before = mouse.memory(0x00, 0x4C);
+
+        let mut table = DpiTable::read(&device).unwrap();
+        table.set_stage_count(3).unwrap();
+        assert!(table.write(&device).is_err());
+        assert_eq!(mouse.memory(0x00, 0x4C), before);
+
+        // Without a trusted count every slot can be active or changed
+        let mut table = DpiTable::read(&device).unwrap();
+        table.set_active_stage(MAX_DPI_STAGES).unwrap();
+        table.write(&device).unwrap();
+        apply_dpi_setting(&device, MAX_DPI_STAGES, Some(900), None, None).unwrap();
+    }
+
+    // Loses or fails one write report on the way to a simulated mouse
+    struct FaultyWrite {
+        mouse: SimulatedMouse,
+        writes: AtomicUsize,
+        faulty: usize,
+        fail: bool,
+    }
+
+    impl FaultyWrite {
+        fn device(faulty: usize, fail: bool) -> (SimulatedMouse, Device) {
+            let mouse = SimulatedMouse::new();
+            let transport = FaultyWrite {
+                mouse: mouse.clone(),
+                writes: AtomicUsize::new(0),
+                faulty,
+                fail,
+            };
+            let mut device = Device::from_transport(transport, true);
+            device.set_packet_interval(Duration::ZERO);
+            (mouse, device)
+        }
+    }
+
+    impl Transport for FaultyWrite {
+        fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+            if report[1] == 0x07 && self.writes.fetch_add(1, Ordering::Relaxed) == self.faulty {
+                if self.fail {
+                    return Err(std::io::Error::other("write failed").into());
+                }
+                return Ok(());
+            }
+            self.mouse.send_feature_report(report)
+        }
+
+        fn write(&self, data: &[u8]) -> Result<usize> {
+            self.mouse.write(data)
+        }
+
+        fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+            self.mouse.read_timeout(buf, timeout_ms)
+        }
+    }
+
+    // Three changed DPI pairs and a new active stage, four writes in all
+    fn changed_table(device: &Device) -> DpiTable {
+        let mut table = DpiTable::read(device).unwrap();
+        for stage in [1, 3, 5] {
+            let color = table.stage(stage).unwrap().color().clone();
+            table
+                .set_stage(stage, StageSettings::new(DpiStage::new(1000, 1000), color))
+                .unwrap();
+        }
+        table.set_active_stage(2).unwrap();
+        table
+    }
+
+    #[test]
+    fn lost_write_is_rolled_back() {
+        let (mouse, device) = FaultyWrite::device(1, false);
+        let original = DpiTable::read(&device).unwrap();
+        let before = mouse.memory(0x00, 0x4C);
+
+        let result = changed_table(&device).write(&device);
+
+        assert!(matches!(result, Err(MadRError::VerificationFailed(_))));
+        assert_eq!(DpiTable::read(&device).unwrap(), original);
+        assert_eq!(mouse.memory(0x00, 0x4C), before);
+    }
+
+    #[test]
+    fn failed_write_is_rolled_back() {
+        let (mouse, device) = FaultyWrite::device(2, true);
+        let original = DpiTable::read(&device).unwrap();
+        let before = mouse.memory(0x00, 0x4C);
+
+        let result = changed_table(&device).write(&device);
+
+        assert!(matches!(result, Err(MadRError::Io(_))));
+        assert_eq!(DpiTable::read(&device).unwrap(), original);
+        assert_eq!(mouse.memory(0x00, 0x4C), before);
+    }
+}
@@ -1,10 +1,25 @@
 pub mod battery;
+pub mod blocks;
+#[cfg(feature = "experimental")]
+pub mod buttons;
+pub mod capture;
 pub mod debounce;
 pub mod device;
 pub mod dpi;
+#[cfg(feature = "experimental")]
+pub mod lighting;
+pub mod lod;
+pub mod packet;
 pub mod performance;
+pub mod replay;
+pub mod reset;
 pub mod sensor;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod sleep;
+pub mod snapshot;
+pub mod trace;
+pub mod transport;
 
 use thiserror::Error;
 
@@ -15,12 +30,33 @@ pub enum MadRError {
     HidApiInit(#[from] hidapi::HidError),
     #[error("No compatible device found")]
     DeviceNotFound,
+    #[error(
+        "Permission denied opening {0}, check that a udev rule gives your user access to the device"
+    )]
+    PermissionDenied(String),
+    #[error(
+        "Failed to open {path}: {source}. If this is a permission problem, check that a udev rule gives your user access to the device"
+    )]
+    OpenFailed {
+        path: String,
+        source: hidapi::HidError,
+    },
+    #[error("Mouse is not responding, it may be asleep or turned off. Move it to wake it up")]
+    MouseAsleep,
+    #[error("Device did not respond")]
+    NoResponse,
     #[error("Invalid battery report format")]
     InvalidBatteryFormat,
     #[error("Invalid sensor report format")]
     InvalidSensorFormat,
     #[error("Invalid sensor setting: {0}")]
     InvalidSensorSetting(String),
+    #[error("Invalid lighting setting: {0}")]
+    InvalidLightingSetting(String),
+    #[error("Invalid LOD setting: {0}")]
+    InvalidLodSetting(String),
+    #[error("Invalid button action: {0}")]
+    InvalidButtonAction(String),
     #[error("Invalid sleep timeout: {0}")]
     InvalidSleepTimeout(String),
     #[error("Invalid debounce value: {0}")]
@@ -33,6 +69,22 @@ pub enum MadRError {
     InvalidPerformanceSetting(String),
     #[error("Invalid debounce setting: {0}")]
     InvalidDebounceSetting(String),
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(String),
+    #[error("Checksum mismatch: expected {expected:#04x}, got {actual:#04x}")]
+    ChecksumMismatch { expected: u8, actual: u8 },
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("Invalid trace: {0}")]
+    InvalidTrace(String),
+    #[error("Invalid capture: {0}")]
+    InvalidCapture(String),
+    #[error("Replay mismatch: {0}")]
+    ReplayMismatch(String),
+    #[error("Verification failed: {0}")]
+    VerificationFailed(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, MadRError>;
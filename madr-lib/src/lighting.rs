@@ -0,0 +1,205 @@
+// DPI indicator lighting
+// The effect settings follow the RGB stage table in a block of one-byte settings at 0x4C:
+// 0x4C effect, 0x4E brightness, 0x50 speed and 0x52, which is always 0x01 and left alone.
+// The per-stage colors themselves are set through the DPI stages (see dpi.rs).
+// Only the colors are known to work: the effect block comes from no decoded capture of the
+// web hub, so the whole module needs the `experimental` feature.
+// Brightness and speed may read 0 while the effect is off.
+
+use crate::device::Device;
+use crate::packet;
+use crate::{MadRError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+pub const MAX_BRIGHTNESS: u8 = 10;
+pub const MAX_SPEED: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightingEffect {
+    Off = 0,
+    #[default]
+    Static = 1,
+    Breathing = 2,
+}
+
+impl fmt::Display for LightingEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightingEffect::Off => write!(f, "off"),
+            LightingEffect::Static => write!(f, "static"),
+            LightingEffect::Breathing => write!(f, "breathing"),
+        }
+    }
+}
+
+impl FromStr for LightingEffect {
+    type Err = MadRError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(LightingEffect::Off),
+            "static" => Ok(LightingEffect::Static),
+            "breathing" => Ok(LightingEffect::Breathing),
+            _ => Err(MadRError::InvalidLightingSetting(s.into())),
+        }
+    }
+}
+
+impl TryFrom<u8> for LightingEffect {
+    type Error = MadRError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(LightingEffect::Off),
+            1 => Ok(LightingEffect::Static),
+            2 => Ok(LightingEffect::Breathing),
+            _ => Err(MadRError::InvalidLightingSetting(format!(
+                "Unknown effect {}",
+                value
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lighting {
+    effect: LightingEffect,
+    brightness: u8,
+    speed: u8,
+}
+
+/// madr's default, not read from a factory reset mouse
+impl Default for Lighting {
+    fn default() -> Self {
+        Self::new(LightingEffect::Static, MAX_BRIGHTNESS, 3)
+    }
+}
+
+impl Lighting {
+    pub fn new(effect: LightingEffect, brightness: u8, speed: u8) -> Self {
+        Self {
+            effect,
+            brightness,
+            speed,
+        }
+    }
+
+    pub fn effect(&self) -> LightingEffect {
+        self.effect
+    }
+
+    /// Brightness from 1 to [`MAX_BRIGHTNESS`], or 0 with the effect off
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Breathing speed from 1 to [`MAX_SPEED`], or 0 with the effect off
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    /// Read lighting settings from device
+    pub fn read(device: &Device) -> Result<Self> {
+        let block = device.read_block(0x4C, 6)?;
+        Self::from_bytes(&block)
+    }
+
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self> {
+        let fields = packet::fields(data, 2)?;
+        if fields.len() < 3 {
+            return Err(MadRError::InvalidLightingSetting(
+                "Unexpected lighting report length".into(),
+            ));
+        }
+
+        Ok(Self {
+            effect: LightingEffect::try_from(fields[0][0])?,
+            brightness: fields[1][0],
+            speed: fields[2][0],
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        // Whatever an unlit LED holds is fine, so turning it off never needs other values
+        let min = if self.effect == LightingEffect::Off {
+            0
+        } else {
+            1
+        };
+
+        if !(min..=MAX_BRIGHTNESS).contains(&self.brightness) {
+            return Err(MadRError::InvalidLightingSetting(format!(
+                "Brightness must be between {} and {}",
+                min, MAX_BRIGHTNESS
+            )));
+        }
+
+        if !(min..=MAX_SPEED).contains(&self.speed) {
+            return Err(MadRError::InvalidLightingSetting(format!(
+                "Speed must be between {} and {}",
+                min, MAX_SPEED
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply lighting settings to device
+pub fn apply_settings(device: &Device, settings: &Lighting) -> Result<()> {
+    settings.validate()?;
+
+    device.update_settings(0x4C, 8, |values| {
+        values[0] = settings.effect as u8;
+        values[1] = settings.brightness;
+        values[2] = settings.speed;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimulatedMouse;
+
+    #[test]
+    fn unlit_block_decodes() {
+        let (mouse, device) = SimulatedMouse::device();
+        for (i, value) in [0x00, 0x00, 0x00].into_iter().enumerate() {
+            mouse.set_memory(0x4C + i as u8 * 2, &[value, packet::checksum(&[value])]);
+        }
+
+        assert_eq!(
+            Lighting::read(&device).unwrap(),
+            Lighting::new(LightingEffect::Off, 0, 0)
+        );
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let (mouse, device) = SimulatedMouse::device();
+        let reserved = mouse.memory(0x52, 2);
+
+        let settings = Lighting::new(LightingEffect::Breathing, 4, MAX_SPEED);
+        apply_settings(&device, &settings).unwrap();
+
+        assert_eq!(Lighting::read(&device).unwrap(), settings);
+        assert_eq!(mouse.memory(0x52, 2), reserved);
+    }
+
+    #[test]
+    fn zero_is_only_accepted_with_the_effect_off() {
+        let (_, device) = SimulatedMouse::device();
+
+        assert!(apply_settings(&device, &Lighting::new(LightingEffect::Static, 0, 3)).is_err());
+        apply_settings(&device, &Lighting::new(LightingEffect::Off, 0, 0)).unwrap();
+
+        // Turning the LED off again keeps whatever it reads
+        let current = Lighting::read(&device).unwrap();
+        apply_settings(
+            &device,
+            &Lighting::new(LightingEffect::Off, current.brightness(), current.speed()),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+// Lift-off distance (LOD) / silent height
+// Assumed to be a single value at 0x0A, right after the performance block: 0x01 for 1 mm
+// and 0x02 for 2 mm. No capture of the web hub changing the LOD has been decoded yet, so
+// the address is unconfirmed; the sensor block around 0xB5 is the other candidate.
+// Reading is harmless, but `info lod` and writing are only built with the `experimental`
+// feature, and dumps and capture decodes mark the block unconfirmed.
+
+use crate::device::Device;
+use crate::packet;
+#[cfg(feature = "experimental")]
+use crate::packet::Packet;
+use crate::{MadRError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LiftOffDistance {
+    #[default]
+    Low = 1,
+    High = 2,
+}
+
+impl fmt::Display for LiftOffDistance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LiftOffDistance::Low => write!(f, "1mm"),
+            LiftOffDistance::High => write!(f, "2mm"),
+        }
+    }
+}
+
+impl FromStr for LiftOffDistance {
+    type Err = MadRError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "1mm" | "1" | "low" => Ok(LiftOffDistance::Low),
+            "2mm" | "2" | "high" => Ok(LiftOffDistance::High),
+            _ => Err(MadRError::InvalidLodSetting(s.into())),
+        }
+    }
+}
+
+impl TryFrom<u8> for LiftOffDistance {
+    type Error = MadRError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(LiftOffDistance::Low),
+            2 => Ok(LiftOffDistance::High),
+            _ => Err(MadRError::InvalidLodSetting(value.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lod {
+    distance: LiftOffDistance,
+}
+
+impl Lod {
+    /// Read lift-off distance from device, from an address that is not confirmed yet
+    pub fn read(device: &Device) -> Result<Self> {
+        let block = device.read_block(0x0A, 2)?;
+        let fields = packet::fields(&block, 2)?;
+
+        let distance = LiftOffDistance::try_from(fields[0][0])?;
+        Ok(Self { distance })
+    }
+
+    pub fn distance(&self) -> LiftOffDistance {
+        self.distance
+    }
+}
+
+#[cfg(feature = "experimental")]
+fn get_lod_report(distance: LiftOffDistance) -> Packet {
+    Packet::write_fields(0x0A, &[&[distance as u8]])
+}
+
+/// Apply lift-off distance to device
+#[cfg(feature = "experimental")]
+pub fn apply_setting(device: &Device, distance: LiftOffDistance) -> Result<()> {
+    let report = get_lod_report(distance);
+    device.send(&report)?;
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "experimental"))]
+mod tests {
+    use super::*;
+    use crate::sim::SimulatedMouse;
+
+    #[test]
+    fn distance_round_trips() {
+        let (_, device) = SimulatedMouse::device();
+
+        for distance in [LiftOffDistance::High, LiftOffDistance::Low] {
+            apply_setting(&device, distance).unwrap();
+            assert_eq!(Lod::read(&device).unwrap().distance(), distance);
+        }
+    }
+}
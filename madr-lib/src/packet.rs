@@ -0,0 +1,248 @@
+// Codec for the 17-byte 0x08 report family used by every setting.
+//
+// | Index | Content                                               |
+// |-------|-------------------------------------------------------|
+// | 0     | Report ID, always `08`                                |
+// | 1     | Command (`07` write, `08` read, `04` battery)         |
+// | 2-3   | Reserved, `00 00`                                     |
+// | 4     | Address                                               |
+// | 5     | Payload length                                        |
+// | 6-15  | Payload, zero padded                                  |
+// | 16    | Trailer: `0x55 - sum(bytes 0..16)` (wrapping)         |
+//
+// Values in the payload are stored as fields followed by a check byte of
+// `0x55 - sum(field)`: one-byte settings take two bytes, DPI and RGB entries take four.
+
+use crate::{MadRError, Result};
+
+pub const REPORT_ID: u8 = 0x08;
+pub const PACKET_LEN: usize = 17;
+pub const MAX_PAYLOAD: usize = 10;
+
+/// `0x55` minus the wrapping sum of `bytes`, used for both field and trailer checks
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0x55u8, |acc, b| acc.wrapping_sub(*b))
+}
+
+/// Split raw memory into fields of `width - 1` value bytes, verifying each check byte
+pub fn fields(bytes: &[u8], width: usize) -> Result<Vec<&[u8]>> {
+    if width < 2 || !bytes.len().is_multiple_of(width) {
+        return Err(MadRError::InvalidPacket(format!(
+            "{} bytes do not split into {}-byte fields",
+            bytes.len(),
+            width
+        )));
+    }
+
+    bytes
+        .chunks(width)
+        .map(|field| {
+            let (value, check) = field.split_at(width - 1);
+            let expected = checksum(value);
+            if check[0] != expected {
+                return Err(MadRError::ChecksumMismatch {
+                    expected,
+                    actual: check[0],
+                });
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Battery = 0x04,
+    Write = 0x07,
+    Read = 0x08,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = MadRError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0x04 => Ok(Command::Battery),
+            0x07 => Ok(Command::Write),
+            0x08 => Ok(Command::Read),
+            _ => Err(MadRError::InvalidPacket(format!(
+                "Unknown command: {:#04x}",
+                value
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    command: Command,
+    address: u8,
+    length: u8,
+    payload: Vec<u8>,
+}
+
+impl Packet {
+    /// Request `length` bytes starting at `address`
+    pub fn read(address: u8, length: u8) -> Self {
+        Self {
+            command: Command::Read,
+            address,
+            length,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Write raw bytes (check bytes included) starting at `address`
+    ///
+    /// # Panics
+    /// If `payload` is longer than [`MAX_PAYLOAD`]
+    pub fn write(address: u8, payload: Vec<u8>) -> Self {
+        assert!(payload.len() <= MAX_PAYLOAD, "payload too long");
+        Self {
+            command: Command::Write,
+            address,
+            length: payload.len() as u8,
+            payload,
+        }
+    }
+
+    /// Write `fields` starting at `address`, appending each field's check byte
+    pub fn write_fields(address: u8, fields: &[&[u8]]) -> Self {
+        let payload = fields
+            .iter()
+            .flat_map(|field| field.iter().copied().chain([checksum(field)]))
+            .collect();
+
+        Self::write(address, payload)
+    }
+
+    /// Request battery status
+    pub fn battery() -> Self {
+        Self {
+            command: Command::Battery,
+            address: 0x00,
+            length: 0x00,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    /// Raw payload bytes, check bytes included
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Split the payload into fields of `width - 1` value bytes, verifying each check byte
+    pub fn fields(&self, width: usize) -> Result<Vec<&[u8]>> {
+        fields(&self.payload, width)
+    }
+
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        let mut report = [0u8; PACKET_LEN];
+        report[0] = REPORT_ID;
+        report[1] = self.command as u8;
+        report[4] = self.address;
+        report[5] = self.length;
+        report[6..6 + self.payload.len()].copy_from_slice(&self.payload);
+        report[PACKET_LEN - 1] = checksum(&report[..PACKET_LEN - 1]);
+        report
+    }
+
+    /// Decode a report, verifying its trailer checksum.
+    /// Battery responses carry no length, so their whole body is kept as payload.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < PACKET_LEN || data[0] != REPORT_ID {
+            return Err(MadRError::InvalidPacket(format!(
+                "Expected a {}-byte report starting with {:#04x}, got {:02x?}",
+                PACKET_LEN, REPORT_ID, data
+            )));
+        }
+
+        let expected = checksum(&data[..PACKET_LEN - 1]);
+        if data[PACKET_LEN - 1] != expected {
+            return Err(MadRError::ChecksumMismatch {
+                expected,
+                actual: data[PACKET_LEN - 1],
+            });
+        }
+
+        let command = Command::try_from(data[1])?;
+        let length = data[5];
+        let payload_len = match command {
+            Command::Battery => MAX_PAYLOAD,
+            _ if length as usize <= MAX_PAYLOAD => length as usize,
+            _ => {
+                return Err(MadRError::InvalidPacket(format!(
+                    "Payload length {} exceeds {}",
+                    length, MAX_PAYLOAD
+                )));
+            }
+        };
+
+        Ok(Self {
+            command,
+            address: data[4],
+            length,
+            payload: data[6..6 + payload_len].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_round_trips() {
+        let packet = Packet::write_fields(0xA9, &[&[0x08], &[0x01]]);
+        let report = packet.encode();
+
+        assert_eq!(
+            report,
+            [
+                0x08, 0x07, 0x00, 0x00, 0xA9, 0x04, 0x08, 0x4D, 0x01, 0x54, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0xEF
+            ]
+        );
+        assert_eq!(Packet::decode(&report).unwrap(), packet);
+        assert_eq!(packet.fields(2).unwrap(), [[0x08], [0x01]]);
+    }
+
+    #[test]
+    fn read_round_trips() {
+        let packet = Packet::read(0x02, 8);
+        let decoded = Packet::decode(&packet.encode()).unwrap();
+
+        assert_eq!(decoded.command(), Command::Read);
+        assert_eq!(decoded.address(), 0x02);
+        assert_eq!(decoded.length(), 8);
+    }
+
+    #[test]
+    fn decode_rejects_bad_trailer() {
+        let mut report = Packet::read(0x00, 6).encode();
+        report[PACKET_LEN - 1] ^= 0xFF;
+
+        assert!(matches!(
+            Packet::decode(&report),
+            Err(MadRError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn fields_reject_bad_check_byte() {
+        assert!(fields(&[0x08, 0x4D, 0x01, 0x55], 2).is_err());
+        assert!(fields(&[0x08, 0x4D, 0x01], 2).is_err());
+    }
+}
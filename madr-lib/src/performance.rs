@@ -1,15 +1,24 @@
 // Performance settings module
-// DPI stage and polling rate share the same report structure (0x08 0x07 0x00 0x00 0x00 0x06)
-// and can be combined into a single configuration report.
+// Polling rate, the number of enabled DPI stages and the active DPI stage share the same
+// block (address 0x00, 6 bytes) and can be combined into a single configuration report.
+// The value at 0x02 was always written as a constant 0x04, with any of the 8 stages active.
+// That it is the number of stages the mouse cycles through is an assumption no capture
+// confirms yet, so changing it and keeping the active stage within it need the
+// `experimental` feature. Without it the value read back is written unchanged.
 
 use crate::device::Device;
+use crate::packet::{self, Packet};
 use crate::{MadRError, Result};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Number of DPI stages the stage table has room for
+pub const MAX_DPI_STAGES: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub enum PollingRate {
     Hz125 = 125,
     Hz250 = 250,
     Hz500 = 500,
+    #[default]
     Hz1000 = 1000,
     Hz2000 = 2000,
     Hz4000 = 4000,
@@ -39,13 +48,15 @@ impl TryFrom<u16> for PollingRate {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Performance {
     dpi_stage: u8,
+    stage_count: u8,
     polling_rate: PollingRate,
 }
 
 impl Performance {
-    pub fn new(dpi_stage: u8, polling_rate: PollingRate) -> Self {
+    pub fn new(dpi_stage: u8, stage_count: u8, polling_rate: PollingRate) -> Self {
         Self {
             dpi_stage,
+            stage_count,
             polling_rate,
         }
     }
@@ -54,28 +65,35 @@ impl Performance {
         self.dpi_stage
     }
 
+    /// Number of enabled DPI stages the mouse cycles through, as far as is known
+    /// (see the module comment)
+    pub fn stage_count(&self) -> u8 {
+        self.stage_count
+    }
+
     pub fn polling_rate(&self) -> PollingRate {
         self.polling_rate
     }
 
     pub fn read(device: &Device) -> Result<Self> {
-        let mut report = [0u8; 17];
-        report[0] = 0x08;
-        report[1] = 0x08;
-        report[5] = 0x06;
-        report[16] = 0x3f;
-
-        device.write(&report)?;
-
-        let mut buf = [0u8; 17];
-        device.read_timeout(&mut buf, 20)?;
-
-        Self::from_bytes(&buf)
+        let block = device.read_block(0x00, 6)?;
+        Self::from_bytes(&block)
     }
 
-    fn from_bytes(data: &[u8]) -> Result<Performance> {
-        let dpi_stage = data[10] + 1; // stored as stage - 1
-        let polling_rate = match data[6] {
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Performance> {
+        let fields = packet::fields(data, 2)?;
+        if fields.len() != 3 {
+            return Err(MadRError::InvalidPerformanceSetting(
+                "Unexpected performance report length".into(),
+            ));
+        }
+
+        let stage_count = fields[1][0];
+        // Stored as stage - 1
+        let dpi_stage = fields[2][0]
+            .checked_add(1)
+            .ok_or_else(|| MadRError::InvalidPerformanceSetting("DPI stage out of range".into()))?;
+        let polling_rate = match fields[0][0] {
             0x08 => PollingRate::Hz125,
             0x04 => PollingRate::Hz250,
             0x02 => PollingRate::Hz500,
@@ -90,14 +108,45 @@ impl Performance {
             }
         };
 
+        // Not validated, an active stage past the stage count is reported as it is
         Ok(Self {
             dpi_stage,
+            stage_count,
             polling_rate,
         })
     }
+
+    fn validate(&self) -> Result<()> {
+        if !(1..=MAX_DPI_STAGES).contains(&self.stage_count) {
+            return Err(MadRError::InvalidPerformanceSetting(format!(
+                "Stage count must be between 1 and {}",
+                MAX_DPI_STAGES
+            )));
+        }
+
+        let last = last_selectable_stage(self.stage_count);
+        if !(1..=last).contains(&self.dpi_stage) {
+            return Err(MadRError::InvalidPerformanceSetting(format!(
+                "DPI stage must be between 1 and {}",
+                last
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Highest stage that can be made active with `stage_count` stages enabled: the stage count
+/// with the `experimental` feature, any stage of the table without it
+pub fn last_selectable_stage(stage_count: u8) -> u8 {
+    if cfg!(feature = "experimental") {
+        stage_count
+    } else {
+        MAX_DPI_STAGES
+    }
 }
 
-fn make_combined_report(dpi_stage: u8, rate: PollingRate) -> Result<Vec<u8>> {
+fn make_combined_report(dpi_stage: u8, stage_count: u8, rate: PollingRate) -> Packet {
     let rate_byte: u8 = match rate {
         PollingRate::Hz125 => 0x08,
         PollingRate::Hz250 => 0x04,
@@ -108,31 +157,107 @@ fn make_combined_report(dpi_stage: u8, rate: PollingRate) -> Result<Vec<u8>> {
         PollingRate::Hz8000 => 0x40,
     };
 
-    Ok(vec![
-        0x08,
-        0x07,
-        0x00,
+    Packet::write_fields(
         0x00,
-        0x00,
-        0x06,
-        rate_byte,                      // byte 6: polling rate
-        0x55u8.wrapping_sub(rate_byte), // byte 7: polling rate checksum
-        0x04,
-        0x51,                               // bytes 8-9: magic bits
-        dpi_stage - 1,                      // byte 10: DPI stage
-        0x55u8.wrapping_sub(dpi_stage - 1), // byte 11: DPI stage checksum
-        0x00,
-        0x00,
-        0x00,
-        0x00,
-        0x41, // bytes 12-16: trailer
-    ])
+        &[
+            &[rate_byte],     // polling rate
+            &[stage_count],   // enabled DPI stages
+            &[dpi_stage - 1], // DPI stage
+        ],
+    )
 }
 
-/// Apply performance settings to device
+/// Apply performance settings to device. Without the `experimental` feature the stage
+/// count must be the one the mouse already has.
 pub fn apply_settings(device: &Device, settings: &Performance) -> Result<()> {
-    let report = make_combined_report(settings.dpi_stage, settings.polling_rate)?;
-    device.send_feature_report(&report)?;
+    #[cfg(not(feature = "experimental"))]
+    if settings.stage_count != Performance::read(device)?.stage_count {
+        return Err(MadRError::InvalidPerformanceSetting(
+            "Changing the stage count needs the experimental feature".into(),
+        ));
+    }
+
+    write_settings(device, settings)
+}
+
+// `apply_settings` without the stage count check, for callers that just read it
+pub(crate) fn write_settings(device: &Device, settings: &Performance) -> Result<()> {
+    settings.validate()?;
+
+    let report = make_combined_report(
+        settings.dpi_stage,
+        settings.stage_count,
+        settings.polling_rate,
+    );
+    device.send(&report)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimulatedMouse;
+
+    #[test]
+    fn settings_round_trip() {
+        let (_, device) = SimulatedMouse::device();
+
+        for rate in [PollingRate::Hz125, PollingRate::Hz500, PollingRate::Hz8000] {
+            let settings = Performance::new(3, 4, rate);
+            apply_settings(&device, &settings).unwrap();
+            assert_eq!(Performance::read(&device).unwrap(), settings);
+        }
+    }
+
+    #[test]
+    fn stage_count_must_fit_the_table() {
+        let (mouse, device) = SimulatedMouse::device();
+        let before = mouse.memory(0x00, 6);
+
+        assert!(apply_settings(&device, &Performance::new(1, 9, PollingRate::Hz1000)).is_err());
+        assert!(apply_settings(&device, &Performance::new(9, 4, PollingRate::Hz1000)).is_err());
+        assert_eq!(mouse.memory(0x00, 6), before);
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn active_stage_must_be_enabled() {
+        let (mouse, device) = SimulatedMouse::device();
+        apply_settings(&device, &Performance::new(2, 3, PollingRate::Hz1000)).unwrap();
+        let before = mouse.memory(0x00, 6);
+
+        assert!(apply_settings(&device, &Performance::new(4, 3, PollingRate::Hz1000)).is_err());
+        assert_eq!(mouse.memory(0x00, 6), before);
+    }
+
+    #[cfg(not(feature = "experimental"))]
+    #[test]
+    fn stage_count_is_kept() {
+        let (mouse, device) = SimulatedMouse::device();
+        let before = mouse.memory(0x00, 6);
+
+        assert!(apply_settings(&device, &Performance::new(1, 3, PollingRate::Hz1000)).is_err());
+        assert_eq!(mouse.memory(0x00, 6), before);
+
+        // Any stage of the table can be active, as before the count was read
+        let settings = Performance::new(7, 4, PollingRate::Hz1000);
+        apply_settings(&device, &settings).unwrap();
+        assert_eq!(Performance::read(&device).unwrap(), settings);
+    }
+
+    #[test]
+    fn active_stage_past_the_count_reads_back() {
+        let (mouse, device) = SimulatedMouse::device();
+
+        // Stage 6 active with 4 stages, as left by the original tool
+        mouse.set_memory(0x02, &[0x04, packet::checksum(&[0x04])]);
+        mouse.set_memory(0x04, &[0x05, packet::checksum(&[0x05])]);
+        let settings = Performance::read(&device).unwrap();
+        assert_eq!((settings.dpi_stage(), settings.stage_count()), (6, 4));
+
+        // Active stage byte 0xFF would be stage 256
+        mouse.set_memory(0x04, &[0xFF, packet::checksum(&[0xFF])]);
+        assert!(Performance::read(&device).is_err());
+    }
+}
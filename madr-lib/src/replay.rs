@@ -0,0 +1,145 @@
+// Replays a recorded session so code built on the crate can be tested against real firmware
+// responses without the mouse attached. Record a fixture with a text-format
+// `TraceTransport` (`madrctl --trace session.txt ...`), then serve it back with
+// `ReplayTransport`. Outgoing reports must match the recording exactly and in order.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::trace::{self, ReportKind, TraceEvent};
+use crate::transport::Transport;
+use crate::{MadRError, Result};
+
+#[derive(Debug)]
+pub struct ReplayTransport {
+    events: Mutex<VecDeque<TraceEvent>>,
+}
+
+impl ReplayTransport {
+    pub fn new(events: Vec<TraceEvent>) -> Self {
+        Self {
+            events: Mutex::new(events.into()),
+        }
+    }
+
+    /// Load a text trace fixture
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Ok(Self::new(trace::parse_text(&text)?))
+    }
+
+    /// Number of recorded reports not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.events().len()
+    }
+
+    fn events(&self) -> MutexGuard<'_, VecDeque<TraceEvent>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn expect_sent(&self, kind: ReportKind, data: &[u8]) -> Result<()> {
+        match self.events().pop_front() {
+            Some(event) if event.kind() == kind && event.data() == data => Ok(()),
+            Some(event) => Err(MadRError::ReplayMismatch(format!(
+                "Sent {} {:02x?}, recording has {} {:02x?}",
+                kind,
+                data,
+                event.kind(),
+                event.data()
+            ))),
+            None => Err(MadRError::ReplayMismatch(format!(
+                "Sent {} {:02x?} past the end of the recording",
+                kind, data
+            ))),
+        }
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        self.expect_sent(ReportKind::Feature, report)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.expect_sent(ReportKind::Output, data)?;
+        Ok(data.len())
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize> {
+        match self.events().pop_front() {
+            Some(event) if event.kind() == ReportKind::Input => {
+                let size = event.data().len().min(buf.len());
+                buf[..size].copy_from_slice(&event.data()[..size]);
+                Ok(size)
+            }
+            Some(event) => Err(MadRError::ReplayMismatch(format!(
+                "Read a report, recording has {} {:02x?}",
+                event.kind(),
+                event.data()
+            ))),
+            None => Err(MadRError::ReplayMismatch(
+                "Read a report past the end of the recording".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::battery::Battery;
+    use crate::device::Device;
+    use crate::dpi;
+    use crate::performance::{Performance, PollingRate};
+
+    const SESSION: &str = include_str!("../fixtures/sim-session.txt");
+
+    fn replay(text: &str) -> Device {
+        let transport = ReplayTransport::new(trace::parse_text(text).unwrap());
+        let mut device = Device::from_transport(transport, false);
+        device.set_packet_interval(Duration::ZERO);
+        device
+    }
+
+    #[test]
+    fn replays_recorded_session() {
+        let device = replay(SESSION);
+
+        let battery = Battery::read(&device).unwrap();
+        assert_eq!(
+            (
+                battery.percentage(),
+                battery.voltage(),
+                battery.is_charging()
+            ),
+            (76, 3950, true)
+        );
+        assert_eq!(
+            Performance::read(&device).unwrap(),
+            Performance::new(1, 4, PollingRate::Hz1000)
+        );
+        dpi::apply_dpi_setting(&device, 3, Some(2000), None, Some("9,8,7")).unwrap();
+
+        // Every recorded report was used
+        match Battery::read(&device) {
+            Err(MadRError::ReplayMismatch(e)) => assert!(e.contains("past the end"), "{}", e),
+            other => panic!("expected the recording to be used up, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_requests_that_differ_from_the_recording() {
+        let device = replay(SESSION);
+        Battery::read(&device).unwrap();
+        Performance::read(&device).unwrap();
+
+        assert!(matches!(
+            dpi::apply_dpi_setting(&device, 3, Some(2050), None, Some("9,8,7")),
+            Err(MadRError::ReplayMismatch(_))
+        ));
+    }
+}
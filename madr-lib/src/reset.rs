@@ -0,0 +1,93 @@
+// Restoring a mouse to madr defaults
+// Every setting the crate models is written back to its `Default` value. These are madr's
+// own choices, not values read from a factory reset mouse. Settings whose writes need the
+// `experimental` feature are only reset with it, so without it the DPI stage count is kept.
+
+use crate::Result;
+#[cfg(feature = "experimental")]
+use crate::buttons::{self, Button};
+use crate::debounce::{self, Debounce};
+use crate::device::Device;
+use crate::dpi::DpiTable;
+#[cfg(feature = "experimental")]
+use crate::lighting::{self, Lighting};
+#[cfg(feature = "experimental")]
+use crate::lod::{self, LiftOffDistance};
+use crate::performance::{self, Performance, PollingRate};
+use crate::sensor::{self, SensorMode};
+use crate::sleep::{self, SleepTimeout};
+
+/// Write the madr default of every setting the crate knows about.
+/// Stops at the first setting that fails, leaving the ones after it untouched.
+pub fn restore_defaults(device: &Device) -> Result<()> {
+    let mut table = DpiTable::default();
+    if !cfg!(feature = "experimental") {
+        table.set_stage_count(Performance::read(device)?.stage_count())?;
+    }
+    table.write(device)?;
+    performance::apply_settings(
+        device,
+        &Performance::new(
+            table.active_stage(),
+            table.stage_count(),
+            PollingRate::default(),
+        ),
+    )?;
+
+    #[cfg(feature = "experimental")]
+    lod::apply_setting(device, LiftOffDistance::default())?;
+    #[cfg(feature = "experimental")]
+    lighting::apply_settings(device, &Lighting::default())?;
+
+    #[cfg(feature = "experimental")]
+    for button in Button::ALL {
+        buttons::apply_setting(device, button, button.default_action())?;
+    }
+
+    debounce::apply_setting(device, Debounce::default())?;
+    #[cfg(feature = "experimental")]
+    sensor::apply_motion_sync(device, true)?;
+    #[cfg(feature = "experimental")]
+    sensor::apply_angle_snapping(device, false)?;
+    #[cfg(feature = "experimental")]
+    sensor::apply_ripple_control(device, false)?;
+    sleep::apply_setting(device, SleepTimeout::default())?;
+    sensor::apply_setting(device, SensorMode::default())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::Sensor;
+    use crate::sim::SimulatedMouse;
+
+    #[test]
+    fn changed_settings_are_restored() {
+        let (mouse, device) = SimulatedMouse::device();
+
+        crate::dpi::apply_dpi_setting(&device, 2, Some(1200), None, Some("1,2,3")).unwrap();
+        let count = if cfg!(feature = "experimental") { 6 } else { 4 };
+        performance::apply_settings(&device, &Performance::new(3, count, PollingRate::Hz4000))
+            .unwrap();
+        debounce::apply_setting(&device, Debounce::Ms20).unwrap();
+        sleep::apply_setting(&device, SleepTimeout::M30).unwrap();
+        sensor::apply_setting(&device, SensorMode::Max).unwrap();
+
+        restore_defaults(&device).unwrap();
+
+        assert_eq!(DpiTable::read(&device).unwrap(), DpiTable::default());
+        assert_eq!(
+            Performance::read(&device).unwrap().polling_rate(),
+            PollingRate::default()
+        );
+        assert_eq!(Debounce::read(&device).unwrap(), Debounce::default());
+        assert_eq!(
+            SleepTimeout::read(&device).unwrap(),
+            SleepTimeout::default()
+        );
+        assert_eq!(Sensor::read(&device).unwrap().mode(), SensorMode::default());
+        assert_eq!(mouse.rejected_reports(), 0);
+    }
+}
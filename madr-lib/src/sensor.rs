@@ -1,4 +1,17 @@
+// Sensor mode lives at 0xB9, the last value in the block at 0xB5 after the sleep
+// confirmation and the sleep timeout it mirrors at 0xB7 (see sleep.rs). The original report
+// always sent the block as 00 06 <mode>, resetting that mirror, so the same report is now
+// filled from the values read back and only the mode changes.
+//
+// The toggles are assumed to be flags in the block at 0xA9 shared with debounce and sleep:
+// motion sync at 0xAB, angle snapping at 0xAF and ripple control at 0xB1. The only source
+// for these addresses is the original debounce report, which always wrote 0x01 to 0xAB and
+// 0x00 to the other two. No capture of the web hub toggling them has been decoded, so any
+// non-zero value reads as on and writing needs the `experimental` feature. They are read
+// separately from the mode (`SensorToggles`), so a wrong guess can't break reading it.
+
 use crate::device::Device;
+use crate::packet;
 use crate::{MadRError, Result};
 use std::fmt;
 use std::str::FromStr;
@@ -55,24 +68,13 @@ pub struct Sensor {
 impl Sensor {
     /// Read sensor configuration from device
     pub fn read(device: &Device) -> Result<Self> {
-        let mut report = [0u8; 17];
-        report[0] = 0x08;
-        report[1] = 0x08;
-        report[4] = 0xB5;
-        report[5] = 0x06;
-        report[16] = 0x8a;
-
-        device.write(&report)?;
-
-        let mut buf = [0u8; 17];
-        device.read_timeout(&mut buf, 20)?;
-
-        let data = &buf;
-        if data.len() < 17 || data[0] != 0x08 || data[1] != 0x08 {
+        let block = device.read_block(0xB5, 6)?;
+        let fields = packet::fields(&block, 2)?;
+        if fields.len() != 3 {
             return Err(MadRError::InvalidSensorFormat);
         }
 
-        let mode = SensorMode::try_from(data[10])?;
+        let mode = SensorMode::try_from(fields[2][0])?;
         Ok(Self { mode })
     }
 
@@ -81,33 +83,142 @@ impl Sensor {
     }
 }
 
-fn get_magic_report(sensor_mode: SensorMode) -> Vec<u8> {
-    let setting = sensor_mode as u8;
-    vec![
-        0x08,
-        0x07,
-        0x00,
-        0x00,
-        0xb5,                         // magic
-        0x06,                         // bytes
-        0x00,    // works with either 00 or 01? after factory reset 00 is correct though
-        0x55,    // 55 - prev
-        0x06,    // magic
-        0x4f,    // magic
-        setting, // sensor setting byte
-        0x55u8.wrapping_sub(setting), // checksum byte
-        0x00,
-        0x00,
-        0x00,
-        0x00,
-        0x8c,
-    ]
+/// Motion sync, angle snapping and ripple control, from unconfirmed addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorToggles {
+    motion_sync: bool,
+    angle_snapping: bool,
+    ripple_control: bool,
 }
 
-/// Apply sensor setting to device
+impl SensorToggles {
+    /// Read the sensor toggles from device
+    pub fn read(device: &Device) -> Result<Self> {
+        // 0xAB motion sync, 0xAD sleep timeout, 0xAF angle snapping, 0xB1 ripple control
+        let block = device.read_block(0xAB, 8)?;
+        let fields = packet::fields(&block, 2)?;
+        let flag = |field: &[u8]| field[0] != 0x00;
+
+        Ok(Self {
+            motion_sync: flag(fields[0]),
+            angle_snapping: flag(fields[2]),
+            ripple_control: flag(fields[3]),
+        })
+    }
+
+    pub fn motion_sync(&self) -> bool {
+        self.motion_sync
+    }
+
+    pub fn angle_snapping(&self) -> bool {
+        self.angle_snapping
+    }
+
+    pub fn ripple_control(&self) -> bool {
+        self.ripple_control
+    }
+}
+
+/// Apply sensor setting to device, keeping the sleep confirmation before it
 pub fn apply_setting(device: &Device, mode: SensorMode) -> Result<()> {
-    let report = get_magic_report(mode);
-    device.send_feature_report(&report)?;
+    device.update_settings(0xB5, 6, |values| values[2] = mode as u8)
+}
+
+/// Turn motion sync on or off
+#[cfg(feature = "experimental")]
+pub fn apply_motion_sync(device: &Device, enabled: bool) -> Result<()> {
+    device.update_settings(0xA9, 10, |values| values[1] = enabled as u8)
+}
+
+/// Turn angle snapping on or off
+#[cfg(feature = "experimental")]
+pub fn apply_angle_snapping(device: &Device, enabled: bool) -> Result<()> {
+    device.update_settings(0xA9, 10, |values| values[3] = enabled as u8)
+}
+
+/// Turn ripple control on or off
+#[cfg(feature = "experimental")]
+pub fn apply_ripple_control(device: &Device, enabled: bool) -> Result<()> {
+    device.update_settings(0xA9, 10, |values| values[4] = enabled as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimulatedMouse;
+    use crate::sleep::{self, SleepTimeout};
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn motion_sync_keeps_the_rest_of_its_block() {
+        let (mouse, device) = SimulatedMouse::device();
+        let before = mouse.memory(0xA9, 10);
+
+        apply_motion_sync(&device, false).unwrap();
+        assert!(!SensorToggles::read(&device).unwrap().motion_sync());
+        apply_motion_sync(&device, true).unwrap();
+        assert!(SensorToggles::read(&device).unwrap().motion_sync());
+
+        let after = mouse.memory(0xA9, 10);
+        assert_eq!([&after[..2], &after[4..]], [&before[..2], &before[4..]]);
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn toggles_round_trip() {
+        let (_, device) = SimulatedMouse::device();
+
+        apply_angle_snapping(&device, true).unwrap();
+        apply_ripple_control(&device, true).unwrap();
+        let sensor = SensorToggles::read(&device).unwrap();
+        assert!(sensor.angle_snapping() && sensor.ripple_control());
+
+        apply_angle_snapping(&device, false).unwrap();
+        let sensor = SensorToggles::read(&device).unwrap();
+        assert!(!sensor.angle_snapping() && sensor.ripple_control());
+    }
+
+    #[test]
+    fn unknown_flag_values_read_as_on() {
+        let (mouse, device) = SimulatedMouse::device();
+        mouse.set_memory(0xAF, &[0x02, packet::checksum(&[0x02])]);
+
+        let sensor = SensorToggles::read(&device).unwrap();
+        assert!(sensor.angle_snapping());
+        assert!(!sensor.ripple_control());
+    }
+
+    #[test]
+    fn mode_is_read_without_the_toggles() {
+        let (mouse, device) = SimulatedMouse::device();
+        apply_setting(&device, SensorMode::Max).unwrap();
 
-    Ok(())
+        // A block that doesn't hold flags only breaks the toggles
+        mouse.set_memory(0xAB, &[0x01, 0x00]);
+
+        assert_eq!(Sensor::read(&device).unwrap().mode(), SensorMode::Max);
+        assert!(SensorToggles::read(&device).is_err());
+    }
+
+    #[test]
+    fn mode_round_trips() {
+        let (_, device) = SimulatedMouse::device();
+
+        for mode in [SensorMode::Max, SensorMode::Competitive, SensorMode::Basic] {
+            apply_setting(&device, mode).unwrap();
+            assert_eq!(Sensor::read(&device).unwrap().mode(), mode);
+        }
+    }
+
+    #[test]
+    fn mode_write_keeps_sleep_confirmation() {
+        let (mouse, device) = SimulatedMouse::device();
+        sleep::apply_setting(&device, SleepTimeout::M5).unwrap();
+        let confirmation = mouse.memory(0xB5, 4);
+
+        apply_setting(&device, SensorMode::Competitive).unwrap();
+
+        assert_eq!(mouse.memory(0xB5, 4), confirmation);
+        assert_eq!(SleepTimeout::read(&device).unwrap(), SleepTimeout::M5);
+    }
 }
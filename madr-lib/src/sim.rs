@@ -0,0 +1,269 @@
+// Software model of a MAD R mouse, for running the crate without hardware.
+// The configuration memory is a flat 256-byte array addressed the same way the 0x08 0x07
+// (write) and 0x08 0x08 (read) reports address it. Every stored value is followed by its
+// `0x55 - value` check byte; DPI and RGB entries (0x0C..0x4C) group three bytes per check.
+// With the `experimental` feature the guessed key assignment table (0x60..0x78, see
+// buttons.rs) is modelled the same way.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::Result;
+use crate::dpi::{DEFAULT_DPI, DEFAULT_RGB, DEFAULT_STAGE_COUNT};
+use crate::packet::{MAX_PAYLOAD, PACKET_LEN, checksum};
+use crate::transport::Transport;
+
+const STAGE_TABLE: std::ops::Range<usize> = 0x0C..0x4C;
+const KEY_TABLE: std::ops::Range<usize> = 0x60..0x78;
+
+// Left, right, middle, back and forward clicks, then DPI cycle
+const DEFAULT_KEYS: [[u8; 3]; 6] = [
+    [0x01, 0x01, 0x00],
+    [0x01, 0x02, 0x00],
+    [0x01, 0x04, 0x00],
+    [0x01, 0x08, 0x00],
+    [0x01, 0x10, 0x00],
+    [0x02, 0x01, 0x00],
+];
+
+fn group_width(address: usize) -> usize {
+    let key_table = cfg!(feature = "experimental") && KEY_TABLE.contains(&address);
+    if STAGE_TABLE.contains(&address) || key_table {
+        4
+    } else {
+        2
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    memory: [u8; 256],
+    pending: VecDeque<Vec<u8>>,
+    battery_percentage: u8,
+    battery_voltage_mv: u16,
+    battery_charging: bool,
+    rejected: usize,
+}
+
+impl State {
+    fn new() -> Self {
+        // Unset values read back as `00 55`; the settings blocks from 0xA9 up are odd-aligned
+        let mut memory = [0u8; 256];
+        for address in (0x00..0xA8).step_by(2).chain((0xA9..0xFF).step_by(2)) {
+            memory[address + 1] = 0x55;
+        }
+
+        let mut state = Self {
+            memory,
+            pending: VecDeque::new(),
+            battery_percentage: 100,
+            battery_voltage_mv: 4200,
+            battery_charging: false,
+            rejected: 0,
+        };
+
+        state.store_pair(0x00, 0x01); // 1000 Hz
+        state.store_pair(0x02, DEFAULT_STAGE_COUNT); // enabled DPI stages
+        state.store_pair(0x04, 0x00); // DPI stage 1 active
+        state.store_pair(0x0A, 0x01); // 1 mm lift-off distance
+
+        for (i, dpi) in DEFAULT_DPI.iter().enumerate() {
+            let raw = dpi / 50 - 1;
+            let high = (((raw >> 8) as u8) << 6) | (((raw >> 8) as u8) << 2);
+            state.store_group(0x0C + i * 4, &[raw as u8, raw as u8, high]);
+        }
+
+        for (i, rgb) in DEFAULT_RGB.iter().enumerate() {
+            state.store_group(0x2C + i * 4, rgb);
+        }
+
+        if cfg!(feature = "experimental") {
+            state.store_pair(0x4C, 0x01); // static lighting
+            state.store_pair(0x4E, 10); // full brightness
+            state.store_pair(0x50, 3); // effect speed
+            state.store_pair(0x52, 0x01);
+        }
+
+        if cfg!(feature = "experimental") {
+            for (i, key) in DEFAULT_KEYS.iter().enumerate() {
+                state.store_group(0x60 + i * 4, key);
+            }
+        }
+
+        state.store_pair(0xA9, 8); // debounce in ms
+        state.store_pair(0xAB, 0x01); // motion sync on
+        state.store_pair(0xAD, 6); // sleep timeout in tens of seconds
+        state.store_pair(0xB7, 6);
+        state.store_pair(0xB9, 0x00); // sensor mode
+
+        state
+    }
+
+    fn store_pair(&mut self, address: usize, value: u8) {
+        self.store_group(address, &[value]);
+    }
+
+    fn store_group(&mut self, address: usize, values: &[u8]) {
+        self.memory[address..address + values.len()].copy_from_slice(values);
+        self.memory[address + values.len()] = checksum(values);
+    }
+
+    fn handle(&mut self, report: &[u8]) {
+        if report.len() != PACKET_LEN
+            || report[0] != 0x08
+            || checksum(&report[..PACKET_LEN - 1]) != report[PACKET_LEN - 1]
+        {
+            self.rejected += 1;
+            return;
+        }
+
+        let address = report[4] as usize;
+        let len = report[5] as usize;
+        let in_range = len <= MAX_PAYLOAD && address + len <= self.memory.len();
+
+        match report[1] {
+            0x07 if in_range && self.fields_valid(address, &report[6..6 + len]) => {
+                self.memory[address..address + len].copy_from_slice(&report[6..6 + len]);
+            }
+            0x08 if in_range => {
+                let mut response = vec![0u8; PACKET_LEN];
+                response[..6].copy_from_slice(&report[..6]);
+                response[6..6 + len].copy_from_slice(&self.memory[address..address + len]);
+                response[PACKET_LEN - 1] = checksum(&response[..PACKET_LEN - 1]);
+                self.pending.push_back(response);
+            }
+            0x04 => {
+                let mut response = vec![0u8; PACKET_LEN];
+                response[0] = 0x08;
+                response[1] = 0x04;
+                response[6] = self.battery_percentage;
+                response[7] = self.battery_charging as u8;
+                response[8..10].copy_from_slice(&self.battery_voltage_mv.to_be_bytes());
+                response[PACKET_LEN - 1] = checksum(&response[..PACKET_LEN - 1]);
+                self.pending.push_back(response);
+            }
+            _ => self.rejected += 1,
+        }
+    }
+
+    // Firmware drops writes whose per-value check bytes don't add up
+    fn fields_valid(&self, address: usize, payload: &[u8]) -> bool {
+        let width = group_width(address);
+        payload.len().is_multiple_of(width)
+            && payload
+                .chunks(width)
+                .all(|group| checksum(&group[..width - 1]) == group[width - 1])
+    }
+}
+
+/// Simulated MAD R mouse implementing [`Transport`].
+///
+/// Clones share the same state, so keep one around to inspect memory after handing
+/// another to [`Device::from_transport`](crate::device::Device::from_transport).
+/// There is no firmware to overwhelm, so pacing can be turned off with
+/// [`Device::set_packet_interval`](crate::device::Device::set_packet_interval).
+#[derive(Debug, Clone)]
+pub struct SimulatedMouse {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for SimulatedMouse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedMouse {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::new())),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Raw configuration memory, check bytes included
+    pub fn memory(&self, address: u8, len: usize) -> Vec<u8> {
+        let start = address as usize;
+        self.state().memory[start..start + len].to_vec()
+    }
+
+    /// Overwrite raw configuration memory without any checksum validation
+    pub fn set_memory(&self, address: u8, bytes: &[u8]) {
+        let start = address as usize;
+        self.state().memory[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Set what the battery command reports
+    pub fn set_battery(&self, percentage: u8, voltage_mv: u16, is_charging: bool) {
+        let mut state = self.state();
+        state.battery_percentage = percentage;
+        state.battery_voltage_mv = voltage_mv;
+        state.battery_charging = is_charging;
+    }
+
+    /// Number of reports dropped for a bad checksum or unknown command
+    pub fn rejected_reports(&self) -> usize {
+        self.state().rejected
+    }
+}
+
+impl Transport for SimulatedMouse {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        self.state().handle(report);
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.state().handle(data);
+        Ok(data.len())
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize> {
+        match self.state().pending.pop_front() {
+            Some(response) => {
+                let size = response.len().min(buf.len());
+                buf[..size].copy_from_slice(&response[..size]);
+                Ok(size)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+impl SimulatedMouse {
+    /// A fresh mouse and a wired device talking to it, with pacing turned off
+    pub(crate) fn device() -> (Self, crate::device::Device) {
+        let mouse = Self::new();
+        let mut device = crate::device::Device::from_transport(mouse.clone(), true);
+        device.set_packet_interval(std::time::Duration::ZERO);
+        (mouse, device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_reports_with_bad_checksums() {
+        let mouse = SimulatedMouse::new();
+        let before = mouse.memory(0x00, 6);
+
+        // Polling rate 500 Hz with a wrong check byte, then with a wrong trailer
+        let mut report = [0u8; PACKET_LEN];
+        report[..8].copy_from_slice(&[0x08, 0x07, 0x00, 0x00, 0x00, 0x02, 0x02, 0x00]);
+        report[PACKET_LEN - 1] = checksum(&report[..PACKET_LEN - 1]);
+        mouse.send_feature_report(&report).unwrap();
+
+        report[7] = checksum(&[0x02]);
+        report[PACKET_LEN - 1] = 0x00;
+        mouse.send_feature_report(&report).unwrap();
+
+        assert_eq!(mouse.memory(0x00, 6), before);
+        assert_eq!(mouse.rejected_reports(), 2);
+    }
+}
@@ -1,61 +1,170 @@
-use crate::Result;
+// The sleep timeout is stored in tens of seconds at 0xAD, in the block at 0xA9 it shares
+// with debounce, and mirrored at 0xB7 next to the sensor mode by a confirmation report.
+
 use crate::device::Device;
+use crate::packet;
+use crate::{MadRError, Result};
+use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 
-fn get_sleep_report(tens_of_seconds: u8) -> Vec<u8> {
-    vec![
-        0x08,
-        0x07,
-        0x00,
-        0x00,
-        0xA9,
-        0x0A,
-        0x04,
-        0x51,
-        0x01,
-        0x54,
-        tens_of_seconds,
-        0x55u8.wrapping_sub(tens_of_seconds),
-        0x00,
-        0x55,
-        0x00,
-        0x55,
-        0xEA,
-    ]
-}
-
-fn get_confirmation_report(tens_of_seconds: u8) -> Vec<u8> {
-    vec![
-        0x08,
-        0x07,
-        0x00,
-        0x00,
-        0xB5,
-        0x06,
-        0x01,
-        0x54,
-        tens_of_seconds,
-        0x55u8.wrapping_sub(tens_of_seconds),
-        0x00,
-        0x55,
-        0x00,
-        0x00,
-        0x00,
-        0x00,
-        0x8C,
-    ]
-}
-
-/// Apply sleep timeout setting to device
-pub fn apply_setting(device: &Device, duration: Duration) -> Result<()> {
-    let time_ms = duration.as_millis() as u32;
-    let tens_of_seconds = (time_ms / 10000) as u8;
-
-    let sleep_pkt = get_sleep_report(tens_of_seconds);
-    device.send_feature_report(&sleep_pkt)?;
-
-    let confirmation = get_confirmation_report(tens_of_seconds);
-    device.send_feature_report(&confirmation)?;
+/// Sleep timeouts offered by the web hub, stored as tens of seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum SleepTimeout {
+    S30 = 3,
+    #[default]
+    M1 = 6,
+    M2 = 12,
+    M3 = 18,
+    M5 = 30,
+    M20 = 120,
+    M25 = 150,
+    M30 = 180,
+}
+
+impl SleepTimeout {
+    pub const ALL: [SleepTimeout; 8] = [
+        SleepTimeout::S30,
+        SleepTimeout::M1,
+        SleepTimeout::M2,
+        SleepTimeout::M3,
+        SleepTimeout::M5,
+        SleepTimeout::M20,
+        SleepTimeout::M25,
+        SleepTimeout::M30,
+    ];
+
+    /// Short name, e.g. "30s" or "5m"
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SleepTimeout::S30 => "30s",
+            SleepTimeout::M1 => "1m",
+            SleepTimeout::M2 => "2m",
+            SleepTimeout::M3 => "3m",
+            SleepTimeout::M5 => "5m",
+            SleepTimeout::M20 => "20m",
+            SleepTimeout::M25 => "25m",
+            SleepTimeout::M30 => "30m",
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(*self as u64 * 10)
+    }
+
+    /// Read sleep timeout from device
+    pub fn read(device: &Device) -> Result<Self> {
+        let block = device.read_block(0xAD, 2)?;
+        let fields = packet::fields(&block, 2)?;
+
+        Self::try_from(fields[0][0])
+    }
+}
+
+impl fmt::Display for SleepTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SleepTimeout {
+    type Err = MadRError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s.to_lowercase())
+            .ok_or_else(|| MadRError::InvalidSleepTimeout(s.into()))
+    }
+}
+
+impl TryFrom<u8> for SleepTimeout {
+    type Error = MadRError;
+
+    fn try_from(tens_of_seconds: u8) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| *t as u8 == tens_of_seconds)
+            .ok_or_else(|| {
+                MadRError::InvalidSleepTimeout(format!("{} s", tens_of_seconds as u32 * 10))
+            })
+    }
+}
+
+impl TryFrom<Duration> for SleepTimeout {
+    type Error = MadRError;
+
+    fn try_from(duration: Duration) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.duration() == duration)
+            .ok_or_else(|| MadRError::InvalidSleepTimeout(format!("{:?}", duration)))
+    }
+}
+
+/// Apply sleep timeout setting to device, keeping the other settings in both blocks.
+/// The reports used to write whole blocks, resetting debounce to 4 ms.
+pub fn apply_setting(device: &Device, timeout: SleepTimeout) -> Result<()> {
+    let tens_of_seconds = timeout as u8;
+
+    device.update_settings(0xA9, 10, |values| values[2] = tens_of_seconds)?;
+
+    // Confirmation
+    device.update_settings(0xB5, 6, |values| {
+        values[0] = 0x01;
+        values[1] = tens_of_seconds;
+    })?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::{self, Debounce};
+    use crate::sensor::{self, Sensor, SensorMode};
+    use crate::sim::SimulatedMouse;
+
+    #[test]
+    fn every_timeout_round_trips() {
+        let (mouse, device) = SimulatedMouse::device();
+
+        for timeout in SleepTimeout::ALL {
+            apply_setting(&device, timeout).unwrap();
+            assert_eq!(SleepTimeout::read(&device).unwrap(), timeout);
+            // Mirrored next to the sensor mode
+            assert_eq!(mouse.memory(0xB7, 1), [timeout as u8]);
+        }
+    }
+
+    #[test]
+    fn unsupported_timeout_is_rejected() {
+        let (mouse, device) = SimulatedMouse::device();
+        mouse.set_memory(0xAD, &[0x07, packet::checksum(&[0x07])]);
+
+        assert!(SleepTimeout::read(&device).is_err());
+    }
+
+    #[test]
+    fn sleep_and_debounce_keep_each_other() {
+        let (_, device) = SimulatedMouse::device();
+
+        debounce::apply_setting(&device, Debounce::Ms2).unwrap();
+        apply_setting(&device, SleepTimeout::M20).unwrap();
+        assert_eq!(Debounce::read(&device).unwrap(), Debounce::Ms2);
+
+        debounce::apply_setting(&device, Debounce::Ms15).unwrap();
+        assert_eq!(SleepTimeout::read(&device).unwrap(), SleepTimeout::M20);
+    }
+
+    #[test]
+    fn sleep_keeps_sensor_mode() {
+        let (_, device) = SimulatedMouse::device();
+        sensor::apply_setting(&device, SensorMode::Max).unwrap();
+
+        apply_setting(&device, SleepTimeout::S30).unwrap();
+
+        assert_eq!(Sensor::read(&device).unwrap().mode(), SensorMode::Max);
+        assert_eq!(SleepTimeout::read(&device).unwrap(), SleepTimeout::S30);
+    }
+}
@@ -0,0 +1,280 @@
+// Full configuration memory dumps, for reverse engineering settings that aren't modelled yet.
+//
+// Snapshots are stored as text: 16 bytes per line prefixed by the address, `??` for bytes
+// the device didn't answer for, and `#` comments decoding every known block, e.g.
+//
+//     # 0x0c DPI stages 1+2: 400x400, 800x800
+//     00: 01 54 04 51 00 55 00 55 00 55 00 55 07 07 00 47
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::blocks::{self, Block};
+use crate::device::{ConnectionState, Device};
+use crate::{MadRError, Result};
+
+const MEMORY_SIZE: usize = 0x100;
+const READ_CHUNK: usize = 8;
+const LINE_WIDTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    memory: Vec<Option<u8>>,
+}
+
+/// A byte that differs between two snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    address: u8,
+    before: Option<u8>,
+    after: Option<u8>,
+}
+
+impl Change {
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn before(&self) -> Option<u8> {
+        self.before
+    }
+
+    pub fn after(&self) -> Option<u8> {
+        self.after
+    }
+}
+
+impl Snapshot {
+    /// Read the whole configuration memory. Chunks the device doesn't answer
+    /// properly are left empty instead of failing the dump.
+    pub fn read(device: &Device) -> Result<Self> {
+        match device.probe()? {
+            ConnectionState::Connected => {}
+            ConnectionState::Asleep => return Err(MadRError::MouseAsleep),
+            ConnectionState::Unresponsive => return Err(MadRError::NoResponse),
+        }
+
+        let mut memory = vec![None; MEMORY_SIZE];
+
+        for address in (0..MEMORY_SIZE).step_by(READ_CHUNK) {
+            match device.read_block(address as u8, READ_CHUNK) {
+                Ok(bytes) => {
+                    for (i, b) in bytes.into_iter().enumerate() {
+                        memory[address + i] = Some(b);
+                    }
+                }
+                Err(
+                    MadRError::InvalidPacket(_)
+                    | MadRError::ChecksumMismatch { .. }
+                    | MadRError::NoResponse
+                    | MadRError::MouseAsleep,
+                ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self { memory })
+    }
+
+    pub fn get(&self, address: u8) -> Option<u8> {
+        self.memory[address as usize]
+    }
+
+    /// Raw bytes of `block`, if every one of them was read
+    pub fn block(&self, block: &Block) -> Option<Vec<u8>> {
+        let start = block.address() as usize;
+        self.memory
+            .get(start..start + block.length() as usize)?
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Decode `block`, if it was read in full
+    pub fn describe(&self, block: &Block) -> Option<String> {
+        block.describe(&self.block(block)?)
+    }
+
+    /// Bytes that differ from `other`, in address order
+    pub fn diff(&self, other: &Snapshot) -> Vec<Change> {
+        self.memory
+            .iter()
+            .zip(&other.memory)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(address, (before, after))| Change {
+                address: address as u8,
+                before: *before,
+                after: *after,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let known = blocks::known_blocks();
+
+        for (line, bytes) in self.memory.chunks(LINE_WIDTH).enumerate() {
+            let start = line * LINE_WIDTH;
+
+            for block in &known {
+                if (start..start + LINE_WIDTH).contains(&(block.address() as usize))
+                    && let Some(text) = self.describe(block)
+                {
+                    writeln!(f, "# {:#04x} {}: {}", block.address(), block.name(), text)?;
+                }
+            }
+
+            write!(f, "{:02x}:", start)?;
+            for b in bytes {
+                match b {
+                    Some(b) => write!(f, " {:02x}", b)?,
+                    None => write!(f, " ??")?,
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = MadRError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |line: &str| MadRError::InvalidSnapshot(format!("Invalid line: {}", line));
+        let mut memory = vec![None; MEMORY_SIZE];
+
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (address, bytes) = line.split_once(':').ok_or_else(|| invalid(line))?;
+            let start = usize::from_str_radix(address.trim(), 16).map_err(|_| invalid(line))?;
+
+            for (i, byte) in bytes.split_whitespace().enumerate() {
+                let slot = memory.get_mut(start + i).ok_or_else(|| invalid(line))?;
+                *slot = match byte {
+                    "??" => None,
+                    _ => Some(u8::from_str_radix(byte, 16).map_err(|_| invalid(line))?),
+                };
+            }
+        }
+
+        Ok(Self { memory })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::checksum;
+    use crate::sim::SimulatedMouse;
+    use crate::transport::Transport;
+
+    // A mouse that never answers reads of the last 8 bytes of memory
+    struct ShortMemory(SimulatedMouse);
+
+    impl Transport for ShortMemory {
+        fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+            self.write(report).map(|_| ())
+        }
+
+        fn write(&self, data: &[u8]) -> Result<usize> {
+            if data[1] == 0x08 && data[4] >= 0xF8 {
+                return Ok(data.len());
+            }
+            self.0.write(data)
+        }
+
+        fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+            self.0.read_timeout(buf, timeout_ms)
+        }
+    }
+
+    #[test]
+    fn dump_matches_memory() {
+        let (mouse, device) = SimulatedMouse::device();
+        let snapshot = Snapshot::read(&device).unwrap();
+
+        for address in 0..=0xFFu8 {
+            assert_eq!(snapshot.get(address), Some(mouse.memory(address, 1)[0]));
+        }
+    }
+
+    #[test]
+    fn unanswered_chunks_are_left_empty() {
+        let mut device = Device::from_transport(ShortMemory(SimulatedMouse::new()), true);
+        device.set_packet_interval(std::time::Duration::ZERO);
+        let snapshot = Snapshot::read(&device).unwrap();
+
+        assert!((0xF0..0xF8).all(|a| snapshot.get(a).is_some()));
+        assert!((0xF8..=0xFF).all(|a| snapshot.get(a).is_none()));
+
+        let text = snapshot.to_string();
+        assert!(text.ends_with(" ?? ?? ?? ?? ?? ?? ?? ??\n"));
+        assert_eq!(text.parse::<Snapshot>().unwrap(), snapshot);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let (_, device) = SimulatedMouse::device();
+        let snapshot = Snapshot::read(&device).unwrap();
+
+        let text = snapshot.to_string();
+
+        assert!(text.lines().any(|line| line.starts_with("# 0x0c ")));
+        assert_eq!(text.parse::<Snapshot>().unwrap(), snapshot);
+    }
+
+    #[test]
+    fn missing_bytes_parse_as_unknown() {
+        let snapshot: Snapshot = "# comment\n\n  a8: ?? 08 4d\n".parse().unwrap();
+
+        assert_eq!(snapshot.get(0xA8), None);
+        assert_eq!(snapshot.get(0xA9), Some(0x08));
+        assert_eq!(snapshot.get(0xAA), Some(0x4D));
+        assert_eq!(snapshot.get(0xAB), None);
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        assert!("00 01 02".parse::<Snapshot>().is_err());
+        assert!("zz: 01".parse::<Snapshot>().is_err());
+        assert!("00: 01 xx".parse::<Snapshot>().is_err());
+        assert!("fe: 01 02 03".parse::<Snapshot>().is_err());
+    }
+
+    #[test]
+    fn diff_lists_changed_bytes_in_order() {
+        let (mouse, device) = SimulatedMouse::device();
+        let before = Snapshot::read(&device).unwrap();
+
+        crate::debounce::apply_setting(&device, crate::debounce::Debounce::Ms4).unwrap();
+        mouse.set_memory(0x00, &[0x02, checksum(&[0x02])]);
+        let mut after = Snapshot::read(&device).unwrap();
+        after.memory[0xF0] = None;
+        let unset = before.get(0xF0);
+
+        let changes: Vec<_> = before
+            .diff(&after)
+            .iter()
+            .map(|c| (c.address(), c.before(), c.after()))
+            .collect();
+
+        assert_eq!(
+            changes,
+            [
+                (0x00, Some(0x01), Some(0x02)),
+                (0x01, Some(checksum(&[0x01])), Some(checksum(&[0x02]))),
+                (0xA9, Some(0x08), Some(0x04)),
+                (0xAA, Some(checksum(&[0x08])), Some(checksum(&[0x04]))),
+                (0xF0, unset, None),
+            ]
+        );
+        assert!(after.diff(&after).is_empty());
+    }
+}
@@ -0,0 +1,425 @@
+// Transport wrapper that logs every report going to and coming from the mouse.
+//
+// Text traces have one report per line: seconds since the trace started, direction,
+// report kind and the bytes in hex. An input line with no bytes is a read that timed out.
+//
+//     0.000007 > output 08 08 00 00 00 06 00 00 00 00 00 00 00 00 00 00 3f
+//     0.000734 < input 08 08 00 00 00 06 01 54 04 51 00 55 00 00 00 00 40
+//
+// pcapng traces use the Linux usbmon link type so Wireshark's USB HID dissector can read them.
+// Feature and output reports are written as SET_REPORT control transfers and input reports
+// as interrupt IN transfers; bus and device numbers are placeholders. Reads that timed out
+// are left out, since every request starts with one while draining stale reports.
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::transport::Transport;
+use crate::{MadRError, Result};
+
+const LINKTYPE_USB_LINUX_MMAPPED: u16 = 220;
+const HID_INTERFACE: u8 = 1;
+const INTERRUPT_IN_ENDPOINT: u8 = 0x82;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    Feature,
+    Output,
+    Input,
+}
+
+impl ReportKind {
+    pub fn is_outgoing(&self) -> bool {
+        *self != ReportKind::Input
+    }
+}
+
+impl fmt::Display for ReportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportKind::Feature => write!(f, "feature"),
+            ReportKind::Output => write!(f, "output"),
+            ReportKind::Input => write!(f, "input"),
+        }
+    }
+}
+
+impl FromStr for ReportKind {
+    type Err = MadRError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "feature" => Ok(ReportKind::Feature),
+            "output" => Ok(ReportKind::Output),
+            "input" => Ok(ReportKind::Input),
+            _ => Err(MadRError::InvalidTrace(format!(
+                "Unknown report kind: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// One report from a text trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    elapsed: Duration,
+    kind: ReportKind,
+    data: Vec<u8>,
+}
+
+impl TraceEvent {
+    /// Time since the trace started
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn kind(&self) -> ReportKind {
+        self.kind
+    }
+
+    /// Report bytes, empty for a read that timed out
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Parse a text trace, skipping blank lines and `#` comments
+pub fn parse_text(text: &str) -> Result<Vec<TraceEvent>> {
+    let invalid = |line: &str| MadRError::InvalidTrace(format!("Invalid line: {}", line));
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let elapsed = parts
+                .next()
+                .and_then(|t| Duration::try_from_secs_f64(t.parse().ok()?).ok())
+                .ok_or_else(|| invalid(line))?;
+            let direction = parts.next().ok_or_else(|| invalid(line))?;
+            let kind: ReportKind = parts.next().ok_or_else(|| invalid(line))?.parse()?;
+
+            if direction != if kind.is_outgoing() { ">" } else { "<" } {
+                return Err(invalid(line));
+            }
+
+            let data = parts
+                .map(|b| u8::from_str_radix(b, 16).map_err(|_| invalid(line)))
+                .collect::<Result<Vec<u8>>>()?;
+
+            Ok(TraceEvent {
+                elapsed,
+                kind,
+                data,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Pcapng,
+}
+
+struct TraceWriter {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    start: Instant,
+    events: u64,
+}
+
+impl TraceWriter {
+    fn new(out: Box<dyn Write + Send>, format: TraceFormat) -> Self {
+        Self {
+            out,
+            format,
+            start: Instant::now(),
+            events: 0,
+        }
+    }
+
+    fn record(&mut self, kind: ReportKind, data: &[u8]) -> Result<()> {
+        if self.format == TraceFormat::Pcapng && data.is_empty() {
+            return Ok(());
+        }
+
+        if self.format == TraceFormat::Pcapng && self.events == 0 {
+            write_pcapng_header(&mut self.out)?;
+        }
+
+        match self.format {
+            TraceFormat::Text => {
+                let elapsed = self.start.elapsed().as_secs_f64();
+                let direction = if kind.is_outgoing() { '>' } else { '<' };
+                write!(self.out, "{:.6} {} {}", elapsed, direction, kind)?;
+                for b in data {
+                    write!(self.out, " {:02x}", b)?;
+                }
+                writeln!(self.out)?;
+            }
+            TraceFormat::Pcapng => {
+                let packet = usbmon_packet(self.events, kind, data);
+                write_enhanced_packet(&mut self.out, &packet)?;
+            }
+        }
+
+        self.events += 1;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// [`Transport`] wrapper that logs all traffic through the inner transport
+pub struct TraceTransport<T: Transport> {
+    inner: T,
+    writer: Mutex<TraceWriter>,
+}
+
+impl<T: Transport> TraceTransport<T> {
+    pub fn new(inner: T, out: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        Self {
+            inner,
+            writer: Mutex::new(TraceWriter::new(Box::new(out), format)),
+        }
+    }
+
+    fn record(&self, kind: ReportKind, data: &[u8]) -> Result<()> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(kind, data)
+    }
+}
+
+impl<T: Transport> Transport for TraceTransport<T> {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        self.record(ReportKind::Feature, report)?;
+        self.inner.send_feature_report(report)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.record(ReportKind::Output, data)?;
+        self.inner.write(data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let size = self.inner.read_timeout(buf, timeout_ms)?;
+        self.record(ReportKind::Input, &buf[..size])?;
+        Ok(size)
+    }
+}
+
+fn write_block(out: &mut dyn Write, block_type: u32, body: &[u8]) -> Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total_len = (12 + body.len() + padding) as u32;
+
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total_len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&[0u8; 3][..padding])?;
+    out.write_all(&total_len.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_pcapng_header(out: &mut dyn Write) -> Result<()> {
+    // Section header: byte order magic, version 1.0, unknown section length
+    let mut shb = Vec::new();
+    shb.extend_from_slice(&0x1A2B3C4Du32.to_le_bytes());
+    shb.extend_from_slice(&1u16.to_le_bytes());
+    shb.extend_from_slice(&0u16.to_le_bytes());
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+    write_block(out, 0x0A0D0D0A, &shb)?;
+
+    // Interface description: link type, reserved, no snap length limit
+    let mut idb = Vec::new();
+    idb.extend_from_slice(&LINKTYPE_USB_LINUX_MMAPPED.to_le_bytes());
+    idb.extend_from_slice(&0u16.to_le_bytes());
+    idb.extend_from_slice(&0u32.to_le_bytes());
+    write_block(out, 0x00000001, &idb)
+}
+
+fn write_enhanced_packet(out: &mut dyn Write, packet: &[u8]) -> Result<()> {
+    let micros = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+
+    let mut epb = Vec::new();
+    epb.extend_from_slice(&0u32.to_le_bytes()); // interface ID
+    epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    epb.extend_from_slice(&(micros as u32).to_le_bytes());
+    epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    epb.extend_from_slice(packet);
+    write_block(out, 0x00000006, &epb)
+}
+
+// 64-byte usbmon header (see Documentation/usb/usbmon.rst) followed by the report
+fn usbmon_packet(id: u64, kind: ReportKind, data: &[u8]) -> Vec<u8> {
+    let micros = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+
+    let (event, transfer_type, endpoint, setup) = match kind {
+        ReportKind::Feature | ReportKind::Output => {
+            // SET_REPORT, wValue = report type << 8 | report ID
+            let report_type = if kind == ReportKind::Feature {
+                0x03
+            } else {
+                0x02
+            };
+            let report_id = data.first().copied().unwrap_or(0);
+            let len = (data.len() as u16).to_le_bytes();
+            let setup = [
+                0x21,
+                0x09,
+                report_id,
+                report_type,
+                HID_INTERFACE,
+                0x00,
+                len[0],
+                len[1],
+            ];
+            (b'S', 2u8, 0x00, Some(setup))
+        }
+        ReportKind::Input => (b'C', 1u8, INTERRUPT_IN_ENDPOINT, None),
+    };
+
+    let mut packet = Vec::with_capacity(64 + data.len());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.push(event);
+    packet.push(transfer_type);
+    packet.push(endpoint);
+    packet.push(1); // device address
+    packet.extend_from_slice(&1u16.to_le_bytes()); // bus
+    packet.push(if setup.is_some() { 0 } else { b'-' });
+    packet.push(0); // data present
+    packet.extend_from_slice(&((micros / 1_000_000) as i64).to_le_bytes());
+    packet.extend_from_slice(&((micros % 1_000_000) as i32).to_le_bytes());
+    packet.extend_from_slice(&0i32.to_le_bytes()); // status
+    packet.extend_from_slice(&(data.len() as u32).to_le_bytes()); // urb length
+    packet.extend_from_slice(&(data.len() as u32).to_le_bytes()); // captured length
+    packet.extend_from_slice(&setup.unwrap_or_default());
+    packet.extend_from_slice(&0i32.to_le_bytes()); // interval
+    packet.extend_from_slice(&0i32.to_le_bytes()); // start frame
+    packet.extend_from_slice(&0u32.to_le_bytes()); // transfer flags
+    packet.extend_from_slice(&0u32.to_le_bytes()); // iso descriptors
+    packet.extend_from_slice(data);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::capture::{self, Direction};
+    use crate::sim::SimulatedMouse;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn traced_reads(format: TraceFormat) -> Vec<u8> {
+        let (_, device) = SimulatedMouse::device();
+        let out = SharedBuffer::default();
+        let device = device.wrap_transport(|t| TraceTransport::new(t, out.clone(), format));
+
+        device.read_block(0x00, 6).unwrap();
+        device.read_block(0xB5, 6).unwrap();
+
+        out.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn pcapng_leaves_out_empty_reads() {
+        let bytes = traced_reads(TraceFormat::Pcapng);
+
+        // Section header, interface description, then one packet per report
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let block_len = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+            blocks.push(u32::from_le_bytes(
+                bytes[offset..offset + 4].try_into().unwrap(),
+            ));
+            offset += block_len as usize;
+        }
+        assert_eq!(blocks, [0x0A0D0D0A, 0x00000001, 6, 6, 6, 6]);
+
+        let directions: Vec<_> = capture::parse(&bytes)
+            .unwrap()
+            .iter()
+            .map(|r| r.direction())
+            .collect();
+        assert_eq!(
+            directions,
+            [
+                Direction::ToDevice,
+                Direction::FromDevice,
+                Direction::ToDevice,
+                Direction::FromDevice
+            ]
+        );
+    }
+
+    #[test]
+    fn text_keeps_empty_reads() {
+        let text = String::from_utf8(traced_reads(TraceFormat::Text)).unwrap();
+        let events = parse_text(&text).unwrap();
+
+        assert_eq!(events.len(), 6);
+        assert!(events[0].data().is_empty());
+        assert!(events[3].data().is_empty());
+    }
+
+    #[test]
+    fn parses_text_trace() {
+        let events = parse_text(
+            "# comment\n\n0.5 > feature 08 08\n0.75 < input\n1.000001 < input 08 04 4c\n",
+        )
+        .unwrap();
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].elapsed(), Duration::from_millis(500));
+        assert_eq!(events[0].kind(), ReportKind::Feature);
+        assert_eq!(events[0].data(), [0x08, 0x08]);
+        assert!(events[1].data().is_empty());
+        assert_eq!(events[2].data(), [0x08, 0x04, 0x4c]);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for line in [
+            "-1.0 > output 08",
+            "NaN > output 08",
+            "inf > output 08",
+            "1e300 > output 08",
+            "0.1 < output 08",
+            "0.1 > output 8g",
+            "0.1 > control 08",
+        ] {
+            assert!(
+                matches!(parse_text(line), Err(MadRError::InvalidTrace(_))),
+                "{}",
+                line
+            );
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Result;
+use hidapi::HidDevice;
+
+/// Low-level report I/O used by [`Device`](crate::device::Device).
+///
+/// The hidapi backend ([`HidTransport`]) is what [`Device::open`](crate::device::Device::open)
+/// uses, but anything that can move 17-byte reports to and from a mouse (a mock, a recorder,
+/// another HID library) can implement this and be passed to
+/// [`Device::from_transport`](crate::device::Device::from_transport).
+/// Transports must be `Send` so a `Device` can be shared between threads.
+pub trait Transport: Send {
+    /// Send a feature report, including the leading report ID
+    fn send_feature_report(&self, report: &[u8]) -> Result<()>;
+
+    /// Write an output report, returning the number of bytes written
+    fn write(&self, data: &[u8]) -> Result<usize>;
+
+    /// Read an input report into `buf`, waiting at most `timeout_ms`.
+    /// Returns 0 if nothing arrived in time.
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        (**self).send_feature_report(report)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        (**self).write(data)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        (**self).read_timeout(buf, timeout_ms)
+    }
+}
+
+/// [`Transport`] backed by a hidapi device handle
+pub struct HidTransport {
+    hid: HidDevice,
+}
+
+impl HidTransport {
+    pub fn new(hid: HidDevice) -> Self {
+        Self { hid }
+    }
+}
+
+impl Transport for HidTransport {
+    fn send_feature_report(&self, report: &[u8]) -> Result<()> {
+        self.hid.send_feature_report(report)?;
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        let size = self.hid.write(data)?;
+        Ok(size)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let size = self.hid.read_timeout(buf, timeout_ms)?;
+        Ok(size)
+    }
+}
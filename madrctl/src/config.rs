@@ -0,0 +1,459 @@
+// Declarative configuration for `madrctl apply`
+//
+// polling_rate = 1000
+// active_stage = 2
+// sensor = "competitive"
+// debounce = 4
+// sleep = "5m"
+//
+// [[stages]]
+// dpi = 800
+// color = "255,0,0"
+//
+// [[stages]]
+// dpi = 1600
+// y_dpi = 1200
+// color = "0,255,0"
+//
+// Every key is optional, missing ones are left as they are. Slots past the stage list keep
+// their current values. With the `experimental` feature the stage list also sets the number
+// of enabled stages, without it the mouse keeps its stage count.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use serde::Deserialize;
+
+use madr_lib::debounce::{self, Debounce};
+use madr_lib::device::Device;
+use madr_lib::dpi::{DpiStage, DpiTable, Rgb, StageSettings};
+use madr_lib::performance::{self, Performance, PollingRate};
+use madr_lib::sensor::{self, Sensor, SensorMode};
+use madr_lib::sleep::{self, SleepTimeout};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    polling_rate: Option<u16>,
+    active_stage: Option<u8>,
+    sensor: Option<String>,
+    debounce: Option<u8>,
+    sleep: Option<String>,
+    stages: Option<Vec<StageConfig>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StageConfig {
+    dpi: u16,
+    y_dpi: Option<u16>,
+    color: Option<String>,
+}
+
+/// The settings a config manages, either as read from the device or as desired
+#[derive(Debug, Default)]
+struct State {
+    table: Option<DpiTable>,
+    polling_rate: Option<PollingRate>,
+    sensor: Option<SensorMode>,
+    debounce: Option<Debounce>,
+    sleep: Option<SleepTimeout>,
+}
+
+struct Drift {
+    setting: String,
+    current: String,
+    desired: String,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.setting,
+            self.current.red(),
+            self.desired.green()
+        )
+    }
+}
+
+impl Config {
+    fn manages_table(&self) -> bool {
+        self.stages.is_some() || self.active_stage.is_some()
+    }
+
+    /// Read the current value of every setting the config manages
+    fn read(&self, device: &Device) -> Result<State> {
+        let mut state = State::default();
+
+        if self.manages_table() {
+            state.table = Some(DpiTable::read(device)?);
+        }
+        if self.polling_rate.is_some() {
+            state.polling_rate = Some(Performance::read(device)?.polling_rate());
+        }
+        if self.sensor.is_some() {
+            state.sensor = Some(Sensor::read(device)?.mode());
+        }
+        if self.debounce.is_some() {
+            state.debounce = Some(Debounce::read(device)?);
+        }
+        if self.sleep.is_some() {
+            state.sleep = Some(SleepTimeout::read(device)?);
+        }
+
+        Ok(state)
+    }
+
+    /// The state the device should be in, starting from `current`
+    fn desired(&self, current: &State, wired: bool) -> Result<State> {
+        let mut table = current.table.clone();
+        if let Some(table) = &mut table {
+            if let Some(stages) = &self.stages {
+                for (i, stage) in stages.iter().enumerate() {
+                    let number = i as u8 + 1;
+                    let color = match &stage.color {
+                        Some(color) => color.parse()?,
+                        None => table
+                            .stage(number)
+                            .map_or(Rgb::new(255, 255, 255), |s| s.color().clone()),
+                    };
+                    let dpi = DpiStage::new(stage.dpi, stage.y_dpi.unwrap_or(stage.dpi));
+
+                    table.set_stage(number, StageSettings::new(dpi, color))?;
+                }
+
+                #[cfg(feature = "experimental")]
+                table.set_stage_count(stages.len() as u8)?;
+            }
+
+            if let Some(stage) = self.active_stage {
+                table.set_active_stage(stage)?;
+            }
+        }
+
+        let polling_rate = self.polling_rate.map(PollingRate::try_from).transpose()?;
+        if wired && polling_rate.is_some_and(|r| r > PollingRate::Hz1000) {
+            return Err(anyhow!(
+                "Wired mouse only supports up to 1000 Hz polling rate."
+            ));
+        }
+
+        Ok(State {
+            table,
+            polling_rate,
+            sensor: self.sensor.as_deref().map(str::parse).transpose()?,
+            debounce: self.debounce.map(Debounce::try_from).transpose()?,
+            sleep: self.sleep.as_deref().map(str::parse).transpose()?,
+        })
+    }
+}
+
+fn drift(current: &State, desired: &State) -> Vec<Drift> {
+    let mut drift = Vec::new();
+    let mut compare = |setting: String, current: String, desired: String| {
+        if current != desired {
+            drift.push(Drift {
+                setting,
+                current,
+                desired,
+            });
+        }
+    };
+
+    if let (Some(current), Some(desired)) = (&current.table, &desired.table) {
+        compare(
+            "stage count".into(),
+            current.stage_count().to_string(),
+            desired.stage_count().to_string(),
+        );
+        compare(
+            "active stage".into(),
+            current.active_stage().to_string(),
+            desired.active_stage().to_string(),
+        );
+
+        for (i, (c, d)) in current.stages().iter().zip(desired.stages()).enumerate() {
+            compare(
+                format!("stage {}", i + 1),
+                format!("{} ({})", c.dpi(), c.color()),
+                format!("{} ({})", d.dpi(), d.color()),
+            );
+        }
+    }
+
+    let show = |value: Option<String>| value.unwrap_or_default();
+    if desired.polling_rate.is_some() {
+        compare(
+            "polling rate".into(),
+            show(current.polling_rate.map(|r| format!("{} Hz", r as u16))),
+            show(desired.polling_rate.map(|r| format!("{} Hz", r as u16))),
+        );
+    }
+    if desired.sensor.is_some() {
+        compare(
+            "sensor".into(),
+            show(current.sensor.map(|s| s.to_string())),
+            show(desired.sensor.map(|s| s.to_string())),
+        );
+    }
+    if desired.debounce.is_some() {
+        compare(
+            "debounce".into(),
+            show(current.debounce.map(|d| format!("{} ms", d as u8))),
+            show(desired.debounce.map(|d| format!("{} ms", d as u8))),
+        );
+    }
+    if desired.sleep.is_some() {
+        compare(
+            "sleep".into(),
+            show(current.sleep.map(|s| s.to_string())),
+            show(desired.sleep.map(|s| s.to_string())),
+        );
+    }
+
+    drift
+}
+
+/// Write the settings of `desired` that differ from `current`
+fn write_changes(device: &Device, current: &State, desired: &State) -> Result<()> {
+    if let Some(table) = &desired.table {
+        if current.table.as_ref() != Some(table) {
+            table.write(device)?;
+        }
+    }
+
+    if let Some(rate) = desired.polling_rate {
+        if current.polling_rate != Some(rate) {
+            let settings = Performance::read(device)?;
+            performance::apply_settings(
+                device,
+                &Performance::new(settings.dpi_stage(), settings.stage_count(), rate),
+            )?;
+        }
+    }
+
+    if let Some(mode) = desired.sensor {
+        if current.sensor != Some(mode) {
+            sensor::apply_setting(device, mode)?;
+        }
+    }
+
+    if let Some(time) = desired.debounce {
+        if current.debounce != Some(time) {
+            debounce::apply_setting(device, time)?;
+        }
+    }
+
+    if let Some(timeout) = desired.sleep {
+        if current.sleep != Some(timeout) {
+            sleep::apply_setting(device, timeout)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Bring the device in line with the config at `path`, or with `check` only report drift
+pub fn apply(device: &Device, path: &Path, check: bool) -> Result<()> {
+    let config: Config = toml::from_str(&std::fs::read_to_string(path)?)?;
+
+    let current = config.read(device)?;
+    let desired = config.desired(&current, device.is_wired())?;
+    let changes = drift(&current, &desired);
+
+    if changes.is_empty() {
+        println!("Mouse matches {}", path.display());
+        return Ok(());
+    }
+
+    for change in &changes {
+        println!("{}", change);
+    }
+
+    if check {
+        return Err(anyhow!(
+            "{} settings differ from {}",
+            changes.len(),
+            path.display()
+        ));
+    }
+
+    write_changes(device, &current, &desired)?;
+
+    let remaining = drift(&config.read(device)?, &desired);
+    if !remaining.is_empty() {
+        for change in &remaining {
+            println!("{}", change);
+        }
+        return Err(anyhow!("{} settings did not take effect", remaining.len()));
+    }
+
+    println!("Applied {} changes", changes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use madr_lib::sim::SimulatedMouse;
+
+    use super::*;
+
+    fn device() -> Device {
+        let mut device = Device::from_transport(SimulatedMouse::new(), true);
+        device.set_packet_interval(Duration::ZERO);
+        device
+    }
+
+    // Config file in the temp directory, removed when dropped
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(name: &str, text: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("madrctl-{}-{}.toml", std::process::id(), name));
+            std::fs::write(&path, text).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    // Names of the settings `text` would change on `device`
+    fn drift_names(device: &Device, text: &str) -> Vec<String> {
+        let config: Config = toml::from_str(text).unwrap();
+        let current = config.read(device).unwrap();
+        let desired = config.desired(&current, device.is_wired()).unwrap();
+        drift(&current, &desired)
+            .into_iter()
+            .map(|d| d.setting)
+            .collect()
+    }
+
+    #[test]
+    fn parses_every_key() {
+        let config: Config = toml::from_str(
+            r#"
+            polling_rate = 500
+            active_stage = 2
+            sensor = "max"
+            debounce = 4
+            sleep = "5m"
+
+            [[stages]]
+            dpi = 800
+
+            [[stages]]
+            dpi = 1600
+            y_dpi = 1200
+            color = "0,255,0"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.polling_rate, Some(500));
+        assert_eq!(config.sleep.as_deref(), Some("5m"));
+        let stages = config.stages.unwrap();
+        assert_eq!(stages[1].y_dpi, Some(1200));
+        assert_eq!(stages[0].color, None);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("polling-rate = 1000").is_err());
+        assert!(toml::from_str::<Config>("[[stages]]\ndpi = 800\nx_dpi = 800").is_err());
+    }
+
+    #[test]
+    fn drift_lists_only_differing_settings() {
+        let device = device();
+        let text = r#"
+            polling_rate = 1000
+            debounce = 2
+            sleep = "5m"
+            stages = [{ dpi = 400 }, { dpi = 900, color = "1,2,3" }]
+        "#;
+        let file = ConfigFile::new("drift", text);
+
+        // The stage list only sets the stage count with the experimental feature
+        let mut expected = vec!["stage count", "stage 2", "debounce", "sleep"];
+        if !cfg!(feature = "experimental") {
+            expected.remove(0);
+        }
+        assert_eq!(drift_names(&device, text), expected);
+
+        apply(&device, &file.0, false).unwrap();
+
+        // Applied, so nothing is left to change
+        assert!(drift_names(&device, text).is_empty());
+        assert_eq!(Debounce::read(&device).unwrap(), Debounce::Ms2);
+        assert_eq!(SleepTimeout::read(&device).unwrap(), SleepTimeout::M5);
+    }
+
+    #[test]
+    fn check_fails_on_drift_without_writing() {
+        let device = device();
+        let file = ConfigFile::new(
+            "check",
+            "debounce = 2
+sleep = \"5m\"",
+        );
+        let before = (
+            Debounce::read(&device).unwrap(),
+            SleepTimeout::read(&device).unwrap(),
+        );
+
+        assert!(apply(&device, &file.0, true).is_err());
+        assert_eq!(
+            (
+                Debounce::read(&device).unwrap(),
+                SleepTimeout::read(&device).unwrap()
+            ),
+            before
+        );
+
+        apply(&device, &file.0, false).unwrap();
+        apply(&device, &file.0, true).unwrap();
+    }
+
+    #[test]
+    fn desired_rejects_invalid_values() {
+        let device = device();
+
+        for text in [
+            "polling_rate = 8000",
+            "sensor = \"fast\"",
+            "sleep = \"7m\"",
+            "active_stage = 9",
+        ] {
+            let config: Config = toml::from_str(text).unwrap();
+            let current = config.read(&device).unwrap();
+            assert!(config.desired(&current, true).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn sensor_mode_leaves_sleep_alone() {
+        let device = device();
+        sleep::apply_setting(&device, SleepTimeout::M20).unwrap();
+
+        let file = ConfigFile::new("sensor", "sensor = \"competitive\"");
+        apply(&device, &file.0, false).unwrap();
+
+        assert_eq!(SleepTimeout::read(&device).unwrap(), SleepTimeout::M20);
+        assert_eq!(
+            Sensor::read(&device).unwrap().mode(),
+            SensorMode::Competitive
+        );
+    }
+}
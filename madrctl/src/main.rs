@@ -1,4 +1,8 @@
-use std::time::Duration;
+mod config;
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 
 use anyhow::anyhow;
 use anyhow::Result;
@@ -7,14 +11,25 @@ use colored::Colorize;
 use clap::{builder::PossibleValuesParser, value_parser, Parser, Subcommand};
 
 use madr_lib::battery::Battery;
+#[cfg(feature = "experimental")]
+use madr_lib::buttons::{self, Button, ButtonAction, Buttons, MouseButton};
+use madr_lib::capture::{self, Direction};
 use madr_lib::debounce::Debounce;
-use madr_lib::performance::PollingRate;
-use madr_lib::sensor::Sensor;
+#[cfg(feature = "experimental")]
+use madr_lib::lighting::{self, Lighting, LightingEffect, MAX_BRIGHTNESS, MAX_SPEED};
+#[cfg(feature = "experimental")]
+use madr_lib::lod::{self, LiftOffDistance, Lod};
+use madr_lib::performance::{PollingRate, MAX_DPI_STAGES};
+use madr_lib::reset;
 use madr_lib::sensor::SensorMode;
+use madr_lib::sensor::{Sensor, SensorToggles};
+use madr_lib::sleep::SleepTimeout;
+use madr_lib::snapshot::Snapshot;
+use madr_lib::trace::{TraceFormat, TraceTransport};
 use madr_lib::{
-    debounce,
-    device::Device,
-    dpi,
+    blocks, debounce,
+    device::{ConnectionState, Device},
+    dpi::{self, DpiTable},
     performance::{self, Performance},
     sensor, sleep,
 };
@@ -23,12 +38,44 @@ use madr_lib::{
 #[command(name = "madrctl")]
 #[command(about = "Control your VXE MAD R series gaming mouse from the command line")]
 struct Cli {
+    /// Device to configure, by serial number or HID path (see `madrctl list`)
+    #[arg(short, long, global = true)]
+    device: Option<String>,
+
+    /// Log every report exchanged with the device to a file, as pcapng if it ends in .pcapng
+    #[arg(long, global = true)]
+    trace: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    #[command(flatten)]
+    Device(DeviceCommand),
+
+    /// List connected devices
+    List,
+
+    /// Show which bytes changed between two snapshots
+    Diff {
+        /// Snapshot taken before the change
+        before: PathBuf,
+        /// Snapshot taken after the change
+        after: PathBuf,
+    },
+
+    /// Decode MAD R reports from a usbmon text, pcap or pcapng capture
+    Decode {
+        /// Capture file
+        file: PathBuf,
+    },
+}
+
+/// Commands that talk to a mouse
+#[derive(Subcommand)]
+enum DeviceCommand {
     /// Configure device settings
     #[clap(subcommand)]
     Set(Set),
@@ -40,6 +87,38 @@ enum Commands {
     /// Get device info
     #[clap(subcommand)]
     Info(Info),
+
+    /// Remap buttons (experimental, the key assignment table is unconfirmed)
+    #[cfg(feature = "experimental")]
+    #[clap(subcommand)]
+    Buttons(ButtonsCmd),
+
+    /// Change the DPI indicator lighting (experimental, the effect block is unconfirmed)
+    #[cfg(feature = "experimental")]
+    #[clap(subcommand)]
+    Lighting(LightingCmd),
+
+    /// Dump the device's configuration memory to a snapshot
+    Dump {
+        /// Snapshot file to write, printed to stdout if not specified
+        file: Option<PathBuf>,
+    },
+
+    /// Bring the mouse in line with a TOML config, writing only what differs
+    Apply {
+        /// Config file
+        file: PathBuf,
+        /// Only report settings that differ, exiting with an error if any do
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Restore every setting to madr's defaults, which may differ from the factory values
+    Reset {
+        /// Confirm overwriting the current configuration
+        #[arg(short, long)]
+        yes: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -53,13 +132,13 @@ enum Set {
     /// Set sleep timeout
     Sleep {
         /// Sleep timeout (inactivity before sleep)
-        #[arg(value_parser = PossibleValuesParser::new(["30s", "1m", "2m", "3m", "5m", "20m", "25m", "30m"]))]
+        #[arg(value_parser = PossibleValuesParser::new(SleepTimeout::ALL.map(|t| t.as_str())))]
         timeout: String,
     },
     /// Set active DPI stage
     DpiStage {
-        /// DPI stage to set active (1-8)
-        #[arg(value_parser = value_parser!(u8).range(1..=8))]
+        /// DPI stage to set active, up to the number of enabled stages with experimental
+        #[arg(value_parser = value_parser!(u8).range(1..=MAX_DPI_STAGES as i64))]
         stage: u8,
     },
     /// Set polling rate
@@ -74,6 +153,31 @@ enum Set {
         #[arg(value_parser = PossibleValuesParser::new(["basic", "competitive", "max"]))]
         preset: String,
     },
+    /// Turn motion sync on or off (experimental, the address is unconfirmed)
+    #[cfg(feature = "experimental")]
+    MotionSync {
+        #[arg(value_parser = PossibleValuesParser::new(["on", "off"]))]
+        state: String,
+    },
+    /// Turn angle snapping on or off (experimental, the address is unconfirmed)
+    #[cfg(feature = "experimental")]
+    AngleSnapping {
+        #[arg(value_parser = PossibleValuesParser::new(["on", "off"]))]
+        state: String,
+    },
+    /// Turn ripple control on or off (experimental, the address is unconfirmed)
+    #[cfg(feature = "experimental")]
+    RippleControl {
+        #[arg(value_parser = PossibleValuesParser::new(["on", "off"]))]
+        state: String,
+    },
+    /// Set lift-off distance (experimental, the address is unconfirmed)
+    #[cfg(feature = "experimental")]
+    Lod {
+        /// Height above the surface at which tracking stops
+        #[arg(value_parser = PossibleValuesParser::new(["1mm", "2mm"]))]
+        distance: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -82,14 +186,23 @@ enum Info {
     Battery,
     /// Get sensor settings
     Sensor,
+    /// Get lift-off distance (experimental, the address is unconfirmed)
+    #[cfg(feature = "experimental")]
+    Lod,
+    /// Get debounce time
+    Debounce,
+    /// Get sleep timeout
+    Sleep,
+    /// Check whether the mouse is responding
+    Connection,
 }
 
 #[derive(Subcommand)]
 enum Dpi {
     /// Change DPI settings for a specific stage
     ModifyStage {
-        /// DPI stage to change (1-8)
-        #[arg(short, long, value_parser = value_parser!(u8).range(1..=8))]
+        /// DPI stage to change, up to the number of enabled stages with experimental
+        #[arg(short, long, value_parser = value_parser!(u8).range(1..=MAX_DPI_STAGES as i64))]
         stage: u8,
         /// X DPI value
         #[arg(short, long, value_parser = value_parser!(u16).range(50..=16000))]
@@ -101,14 +214,223 @@ enum Dpi {
         #[arg(short, long)]
         rgb: Option<String>,
     },
+    /// Enable another DPI stage after the last one
+    /// (experimental, the stage count is unconfirmed)
+    #[cfg(feature = "experimental")]
+    Add {
+        /// X DPI value for the new stage
+        #[arg(short, long, value_parser = value_parser!(u16).range(50..=16000))]
+        x_dpi: Option<u16>,
+        /// Y DPI value for the new stage, if not specified, X DPI will be used
+        #[arg(short, long, requires = "x_dpi", value_parser = value_parser!(u16).range(50..=16000))]
+        y_dpi: Option<u16>,
+        /// RGB color for the new stage in 255,255,255 format
+        #[arg(short, long)]
+        rgb: Option<String>,
+    },
+    /// Disable the last DPI stage (experimental, the stage count is unconfirmed)
+    #[cfg(feature = "experimental")]
+    Remove,
+    /// List every stage with its DPI and color
+    List,
+    /// Show the number of enabled DPI stages
+    Count,
+}
+
+#[cfg(feature = "experimental")]
+#[derive(Subcommand)]
+enum ButtonsCmd {
+    /// Show what every button is assigned to
+    List,
+    /// Assign an action to a button
+    Set {
+        #[arg(value_parser = PossibleValuesParser::new(["left", "right", "middle", "back", "forward", "dpi"]))]
+        button: String,
+        /// left-click, right-click, middle-click, back, forward, dpi-cycle, dpi-up, dpi-down,
+        /// disabled, media:<play-pause|next|previous|stop|mute|volume-up|volume-down>
+        /// or key:[ctrl+][shift+][alt+][gui+]<key>, e.g. key:ctrl+c or key:f5
+        action: String,
+    },
+    /// Restore the default action of a button, or of every button if none is given
+    Reset {
+        #[arg(value_parser = PossibleValuesParser::new(["left", "right", "middle", "back", "forward", "dpi"]))]
+        button: Option<String>,
+    },
+}
+
+#[cfg(feature = "experimental")]
+#[derive(Subcommand)]
+enum LightingCmd {
+    /// Show the current lighting settings
+    Show,
+    /// Change lighting settings, keeping the ones not specified
+    Set {
+        /// Lighting effect
+        #[arg(short, long, value_parser = PossibleValuesParser::new(["off", "static", "breathing"]))]
+        effect: Option<String>,
+        /// Brightness level
+        #[arg(short, long, value_parser = value_parser!(u8).range(1..=MAX_BRIGHTNESS as i64))]
+        brightness: Option<u8>,
+        /// Breathing speed
+        #[arg(short, long, value_parser = value_parser!(u8).range(1..=MAX_SPEED as i64))]
+        speed: Option<u8>,
+    },
+}
+
+fn open_device(selector: Option<&str>) -> Result<Device> {
+    let device = match selector {
+        None => Device::open()?,
+        Some(path) if Device::list()?.iter().any(|d| d.path() == path) => Device::open_path(path)?,
+        Some(serial) => Device::open_serial(serial)?,
+    };
+
+    Ok(device)
+}
+
+fn trace_device(device: Device, path: &Path) -> Result<Device> {
+    let format = match path.extension().and_then(|e| e.to_str()) {
+        Some("pcapng") => TraceFormat::Pcapng,
+        _ => TraceFormat::Text,
+    };
+    let file = File::create(path)?;
+
+    Ok(device.wrap_transport(|t| TraceTransport::new(t, BufWriter::new(file), format)))
+}
+
+fn list_devices() -> Result<()> {
+    let devices = Device::list()?;
+
+    if devices.is_empty() {
+        println!("No compatible devices found");
+    }
+
+    for d in devices {
+        println!(
+            "{} | {} | {} | {}",
+            d.path(),
+            d.serial().unwrap_or("-"),
+            if d.is_wired() {
+                "wired".green()
+            } else {
+                "wireless".cyan()
+            },
+            d.product().unwrap_or("-")
+        );
+    }
+
+    Ok(())
+}
+
+fn list_stages(device: &Device) -> Result<()> {
+    let table = DpiTable::read(device)?;
+
+    for (i, s) in table.stages().iter().enumerate() {
+        let stage = i as u8 + 1;
+        let (r, g, b) = (s.color().r(), s.color().g(), s.color().b());
+
+        let line = format!(
+            "{} {} {:>11} {:>11}",
+            if stage == table.active_stage() {
+                "*"
+            } else {
+                " "
+            },
+            stage,
+            s.dpi().to_string(),
+            s.color().to_string()
+        );
+        let line = if stage <= table.stage_count() {
+            line.normal()
+        } else {
+            line.dimmed()
+        };
+
+        println!("{} {}", line, "    ".on_truecolor(r, g, b));
+    }
+
+    Ok(())
+}
+
+fn diff_snapshots(before: &Path, after: &Path) -> Result<()> {
+    let before: Snapshot = fs::read_to_string(before)?.parse()?;
+    let after: Snapshot = fs::read_to_string(after)?.parse()?;
+
+    let changes = before.diff(&after);
+    if changes.is_empty() {
+        println!("Snapshots are identical");
+    }
+
+    let format_byte = |b: Option<u8>| b.map_or("??".to_string(), |b| format!("{:02x}", b));
+
+    let mut last_block = None;
+    for change in changes {
+        let block = blocks::find(change.address());
+
+        if block != last_block {
+            match &block {
+                Some(b) => {
+                    println!("{} ({:#04x})", b.name().bold(), b.address());
+                    if let (Some(old), Some(new)) = (before.describe(b), after.describe(b)) {
+                        println!("  {} -> {}", old.red(), new.green());
+                    }
+                }
+                None => println!("{}", "unknown".bold()),
+            }
+            last_block = block;
+        }
+
+        println!(
+            "  {:#04x}: {} -> {}",
+            change.address(),
+            format_byte(change.before()).red(),
+            format_byte(change.after()).green()
+        );
+    }
+
+    Ok(())
+}
+
+fn decode_capture(path: &Path) -> Result<()> {
+    let reports = capture::parse(&fs::read(path)?)?;
+
+    if reports.is_empty() {
+        println!("No MAD R reports found in capture");
+    }
+
+    for r in reports {
+        let direction = match r.direction() {
+            Direction::ToDevice => ">".cyan(),
+            Direction::FromDevice => "<".green(),
+        };
+
+        println!(
+            "{:>10.6} {} {}",
+            r.timestamp().as_secs_f64(),
+            direction,
+            r.describe()
+        );
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let device = Device::open()?;
 
-    match cli.command {
-        Commands::Set(cmd) => match cmd {
+    let command = match cli.command {
+        Commands::Device(command) => command,
+        Commands::List => return list_devices(),
+        Commands::Diff { before, after } => return diff_snapshots(&before, &after),
+        Commands::Decode { file } => return decode_capture(&file),
+    };
+
+    let mut device = open_device(cli.device.as_deref())?;
+    if let Some(path) = &cli.trace {
+        device = trace_device(device, path)?;
+    }
+
+    match command {
+        DeviceCommand::Set(cmd) => match cmd {
             Set::Debounce { time } => {
                 let time_val: u8 = time.parse()?;
 
@@ -119,25 +441,14 @@ fn main() -> Result<()> {
                 debounce::apply_setting(&device, Debounce::try_from(time_val)?)?;
             }
             Set::Sleep { timeout } => {
-                let duration = match timeout.as_str() {
-                    "30s" => Duration::from_secs(30),
-                    "1m" => Duration::from_secs(60),
-                    "2m" => Duration::from_secs(120),
-                    "3m" => Duration::from_secs(180),
-                    "5m" => Duration::from_secs(300),
-                    "20m" => Duration::from_secs(1200),
-                    "25m" => Duration::from_secs(1500),
-                    "30m" => Duration::from_secs(1800),
-                    _ => return Err(anyhow!("invalid timeout value: {}", timeout)),
-                };
-
-                sleep::apply_setting(&device, duration)?;
+                let timeout: SleepTimeout = timeout.parse()?;
+                sleep::apply_setting(&device, timeout)?;
             }
             Set::DpiStage { stage } => {
                 let settings = Performance::read(&device)?;
                 performance::apply_settings(
                     &device,
-                    &Performance::new(stage, settings.polling_rate()),
+                    &Performance::new(stage, settings.stage_count(), settings.polling_rate()),
                 )?;
             }
             Set::PollingRate { rate } => {
@@ -154,15 +465,32 @@ fn main() -> Result<()> {
                 let settings = Performance::read(&device)?;
                 performance::apply_settings(
                     &device,
-                    &Performance::new(settings.dpi_stage(), new_rate),
+                    &Performance::new(settings.dpi_stage(), settings.stage_count(), new_rate),
                 )?;
             }
             Set::Sensor { preset } => {
                 let preset: SensorMode = preset.parse()?;
                 sensor::apply_setting(&device, preset)?;
             }
+            #[cfg(feature = "experimental")]
+            Set::MotionSync { state } => {
+                sensor::apply_motion_sync(&device, state == "on")?;
+            }
+            #[cfg(feature = "experimental")]
+            Set::AngleSnapping { state } => {
+                sensor::apply_angle_snapping(&device, state == "on")?;
+            }
+            #[cfg(feature = "experimental")]
+            Set::RippleControl { state } => {
+                sensor::apply_ripple_control(&device, state == "on")?;
+            }
+            #[cfg(feature = "experimental")]
+            Set::Lod { distance } => {
+                let distance: LiftOffDistance = distance.parse()?;
+                lod::apply_setting(&device, distance)?;
+            }
         },
-        Commands::Dpi(cmd) => match cmd {
+        DeviceCommand::Dpi(cmd) => match cmd {
             Dpi::ModifyStage {
                 stage,
                 x_dpi,
@@ -171,8 +499,30 @@ fn main() -> Result<()> {
             } => {
                 dpi::apply_dpi_setting(&device, stage, x_dpi, y_dpi, rgb.as_deref())?;
             }
+            #[cfg(feature = "experimental")]
+            Dpi::Add { x_dpi, y_dpi, rgb } => {
+                let count = dpi::add_stage(&device)?;
+                if x_dpi.is_some() || rgb.is_some() {
+                    dpi::apply_dpi_setting(&device, count, x_dpi, y_dpi, rgb.as_deref())?;
+                }
+                println!("{} DPI stages enabled", count);
+            }
+            #[cfg(feature = "experimental")]
+            Dpi::Remove => {
+                let count = dpi::remove_stage(&device)?;
+                println!("{} DPI stages enabled", count);
+            }
+            Dpi::List => list_stages(&device)?,
+            Dpi::Count => {
+                let settings = Performance::read(&device)?;
+                println!(
+                    "{} DPI stages enabled, stage {} active",
+                    settings.stage_count(),
+                    settings.dpi_stage()
+                );
+            }
         },
-        Commands::Info(cmd) => match cmd {
+        DeviceCommand::Info(cmd) => match cmd {
             Info::Battery => {
                 let b = Battery::read(&device)?;
 
@@ -209,8 +559,141 @@ fn main() -> Result<()> {
                 };
 
                 println!("Sensor is set to {} mode", colored_preset);
+
+                // The toggles' addresses are guessed, so failing to read them isn't fatal
+                match SensorToggles::read(&device) {
+                    Ok(t) => {
+                        let on_off =
+                            |enabled: bool| if enabled { "on".green() } else { "off".cyan() };
+                        println!("Motion sync is {}", on_off(t.motion_sync()));
+                        println!("Angle snapping is {}", on_off(t.angle_snapping()));
+                        println!("Ripple control is {}", on_off(t.ripple_control()));
+                    }
+                    Err(e) => println!(
+                        "Motion sync, angle snapping and ripple control are {} ({})",
+                        "unknown".yellow(),
+                        e
+                    ),
+                }
+            }
+            #[cfg(feature = "experimental")]
+            Info::Lod => {
+                let l = Lod::read(&device)?;
+                println!(
+                    "Lift-off distance is set to {}",
+                    l.distance().to_string().cyan()
+                );
             }
+            Info::Debounce => {
+                let d = Debounce::read(&device)?;
+                println!(
+                    "Debounce time is set to {} ms",
+                    (d as u8).to_string().cyan()
+                );
+            }
+            Info::Sleep => {
+                let timeout = SleepTimeout::read(&device)?;
+                println!("Sleep timeout is set to {}", timeout.to_string().cyan());
+            }
+            Info::Connection => match device.probe()? {
+                ConnectionState::Connected => println!("{}", "Connected".green()),
+                ConnectionState::Asleep => {
+                    println!("{}: move the mouse to wake it up", "Asleep".yellow())
+                }
+                ConnectionState::Unresponsive => println!("{}", "Not responding".red()),
+            },
         },
+        #[cfg(feature = "experimental")]
+        DeviceCommand::Buttons(cmd) => match cmd {
+            ButtonsCmd::List => {
+                let b = Buttons::read(&device)?;
+                for button in Button::ALL {
+                    let action = b.action(button);
+                    let line = format!("{:>8}: {}", button.to_string(), action);
+                    if action == button.default_action() {
+                        println!("{}", line);
+                    } else {
+                        println!("{}", line.cyan());
+                    }
+                }
+            }
+            ButtonsCmd::Set { button, action } => {
+                let button: Button = button.parse()?;
+                let action: ButtonAction = action.parse()?;
+
+                if button == Button::Left && action != ButtonAction::Mouse(MouseButton::Left) {
+                    println!(
+                        "{}: left click will only be available on other buttons",
+                        "warning".yellow()
+                    );
+                }
+
+                buttons::apply_setting(&device, button, action)?;
+            }
+            ButtonsCmd::Reset { button } => {
+                let targets = match button {
+                    Some(b) => vec![b.parse()?],
+                    None => Button::ALL.to_vec(),
+                };
+
+                for b in targets {
+                    buttons::apply_setting(&device, b, b.default_action())?;
+                }
+            }
+        },
+        #[cfg(feature = "experimental")]
+        DeviceCommand::Lighting(cmd) => match cmd {
+            LightingCmd::Show => {
+                let l = Lighting::read(&device)?;
+                println!(
+                    "{} | brightness {}/{} | speed {}/{}",
+                    l.effect().to_string().cyan(),
+                    l.brightness(),
+                    MAX_BRIGHTNESS,
+                    l.speed(),
+                    MAX_SPEED
+                );
+            }
+            LightingCmd::Set {
+                effect,
+                brightness,
+                speed,
+            } => {
+                let current = Lighting::read(&device)?;
+                let effect = match effect {
+                    Some(e) => e.parse::<LightingEffect>()?,
+                    None => current.effect(),
+                };
+
+                lighting::apply_settings(
+                    &device,
+                    &Lighting::new(
+                        effect,
+                        brightness.unwrap_or(current.brightness()),
+                        speed.unwrap_or(current.speed()),
+                    ),
+                )?;
+            }
+        },
+        DeviceCommand::Apply { file, check } => config::apply(&device, &file, check)?,
+        DeviceCommand::Reset { yes } => {
+            if !yes {
+                return Err(anyhow!(
+                    "this overwrites every setting, pass --yes to confirm"
+                ));
+            }
+
+            reset::restore_defaults(&device)?;
+            println!("Restored madr default settings");
+        }
+        DeviceCommand::Dump { file } => {
+            let snapshot = Snapshot::read(&device)?;
+
+            match file {
+                Some(path) => fs::write(path, snapshot.to_string())?,
+                None => print!("{}", snapshot),
+            }
+        }
     }
 
     Ok(())